@@ -1,21 +1,136 @@
 use proc_macro::TokenStream;
 
 use quote::quote;
-use syn::{parse_macro_input, ItemFn};
+use syn::{parse_macro_input, Expr, ItemFn, ReturnType};
 
+/// Runs `main` in a runtime, exiting with its result like a regular `main` would.
+///
+/// Options: `ring_entries = <u32>`, `stack_size = <usize>` and `per_core = <bool>`.
+/// With `per_core = true`, `main` runs on every core and can only return `()` or `Result<(), E>`.
 #[proc_macro_attribute]
-pub fn start(_attr: TokenStream, item: TokenStream) -> TokenStream {
+pub fn start(attr: TokenStream, item: TokenStream) -> TokenStream {
     let item = parse_macro_input!(item as ItemFn);
 
+    let mut ring_entries = None;
+    let mut stack_size = None;
+    let mut per_core = false;
+    let parser = syn::meta::parser(|meta| {
+        if meta.path.is_ident("ring_entries") {
+            ring_entries = Some(meta.value()?.parse::<Expr>()?);
+        } else if meta.path.is_ident("stack_size") {
+            stack_size = Some(meta.value()?.parse::<Expr>()?);
+        } else if meta.path.is_ident("per_core") {
+            per_core = match meta.input.peek(syn::Token![=]) {
+                true => meta.value()?.parse::<syn::LitBool>()?.value,
+                false => true,
+            };
+        } else {
+            return Err(meta.error("unsupported uringy::start option"));
+        }
+        Ok(())
+    });
+    parse_macro_input!(attr with parser);
+
+    if let Some(asyncness) = &item.sig.asyncness {
+        let error = syn::Error::new_spanned(asyncness, "uringy main can't be async");
+        return error.to_compile_error().into();
+    }
+
+    let attributes = &item.attrs;
+    let visibility = &item.vis;
+    let name = &item.sig.ident;
+    let body = &item.block;
+
+    let ring_entries = ring_entries.map(|entries| quote!(.ring_entries(#entries)));
+    let stack_size = stack_size.map(|size| quote!(.stack_size(#size)));
+    let run = match (per_core, &item.sig.output) {
+        (false, ReturnType::Default) => quote!(::uringy::runtime::run_main(builder, move || #body)),
+        (false, ReturnType::Type(_, output)) => {
+            quote!(::uringy::runtime::run_main(builder, move || -> #output #body))
+        }
+        (true, ReturnType::Default) => quote! {
+            ::uringy::runtime::run_main_per_core(builder, move || {
+                #body;
+                ::core::result::Result::<(), ::core::convert::Infallible>::Ok(())
+            })
+        },
+        (true, ReturnType::Type(_, output)) => {
+            quote!(::uringy::runtime::run_main_per_core(builder, move || -> #output #body))
+        }
+    };
+
+    let result = quote! {
+        #(#attributes)*
+        #visibility fn #name() -> ::std::process::ExitCode {
+            let builder = ::uringy::runtime::Builder::new() #ring_entries #stack_size;
+            #run
+        }
+    };
+
+    result.into()
+}
+
+/// Runs the test in a fresh runtime.
+///
+/// Options: `timeout = <Duration>`, `stack_size = <usize>` and `simulated_clock`.
+#[proc_macro_attribute]
+pub fn test(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let item = parse_macro_input!(item as ItemFn);
+
+    let mut timeout = None;
+    let mut stack_size = None;
+    let mut simulated_clock = None;
+    let parser = syn::meta::parser(|meta| {
+        if meta.path.is_ident("timeout") {
+            timeout = Some(meta.value()?.parse::<Expr>()?);
+        } else if meta.path.is_ident("stack_size") {
+            stack_size = Some(meta.value()?.parse::<Expr>()?);
+        } else if meta.path.is_ident("simulated_clock") {
+            simulated_clock = Some(if meta.input.peek(syn::Token![=]) {
+                meta.value()?.parse::<Expr>()?
+            } else {
+                syn::parse_quote!(true)
+            });
+        } else {
+            return Err(meta.error("unsupported uringy::test option"));
+        }
+        Ok(())
+    });
+    parse_macro_input!(attr with parser);
+
+    if let Some(asyncness) = &item.sig.asyncness {
+        let error = syn::Error::new_spanned(asyncness, "uringy tests can't be async");
+        return error.to_compile_error().into();
+    }
+    if !item.sig.inputs.is_empty() {
+        let error = syn::Error::new_spanned(&item.sig.inputs, "uringy tests can't take arguments");
+        return error.to_compile_error().into();
+    }
+
     let attributes = &item.attrs;
     let visibility = &item.vis;
     let signature = &item.sig;
+    let name = &item.sig.ident;
     let body = &item.block;
 
+    let stack_size = stack_size.map(|size| quote!(.stack_size(#size)));
+    let simulated_clock = simulated_clock.map(|flag| quote!(.simulated_clock(#flag)));
+    let timeout = match timeout {
+        Some(timeout) => quote!(::core::option::Option::Some(#timeout)),
+        None => quote!(::core::option::Option::None),
+    };
+
     let result = quote! {
+        #[::core::prelude::v1::test]
         #(#attributes)*
         #visibility #signature {
-            ::uringy::runtime::start(move || #body).unwrap();
+            let builder = ::uringy::runtime::Builder::new() #stack_size #simulated_clock;
+            ::uringy::runtime::run_test(
+                ::core::concat!(::core::module_path!(), "::", ::core::stringify!(#name)),
+                builder,
+                #timeout,
+                move || #body,
+            )
         }
     };
 
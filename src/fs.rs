@@ -1,17 +1,30 @@
 //! Filesystem operations inspired by the standard library.
 
+use std::cell::Cell;
+use std::collections::{HashMap, VecDeque};
 use std::io::{Read, Write};
 use std::os::fd::{AsRawFd, FromRawFd, RawFd};
 use std::os::unix::ffi::OsStrExt;
-use std::path::Path;
-use std::{cmp, ffi, io, mem};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use std::{alloc, cmp, ffi, fmt, io, mem, ops, ptr, slice};
 
 use io_uring::types::FsyncFlags;
 
 use crate::runtime;
 
 /// Handle to an open file.
-pub struct File(RawFd);
+///
+/// Reads and writes go through a file offset kept in userspace, which [io::Seek] moves.
+/// Files opened in append mode, files that aren't regular files or block devices, and files created with [FromRawFd] use the kernel's file offset instead.
+/// Functions that take the file descriptor, like [crate::io::copy], always use the kernel's file offset.
+pub struct File {
+    fd: RawFd,
+    offset: Option<u64>, // kernel's file offset is used while none
+}
 
 impl File {
     /// Opens a file in read-only mode.
@@ -38,7 +51,7 @@ impl File {
     /// Syncs all OS-internal metadata to disk.
     /// Catches errors that would otherwise be ignored when dropping the file.
     pub fn sync_all(&self) -> crate::IoResult<()> {
-        let fd = io_uring::types::Fd(self.0);
+        let fd = io_uring::types::Fd(self.fd);
         let sqe = io_uring::opcode::Fsync::new(fd).build();
         let result = runtime::syscall(sqe)?;
         assert_eq!(result, 0);
@@ -49,7 +62,7 @@ impl File {
     /// Syncs content, but maybe not file metadata to disk.
     /// Reduces disk operations compared to [sync_all].
     pub fn sync_data(&self) -> crate::IoResult<()> {
-        let fd = io_uring::types::Fd(self.0);
+        let fd = io_uring::types::Fd(self.fd);
         let sqe = io_uring::opcode::Fsync::new(fd)
             .flags(FsyncFlags::DATASYNC)
             .build();
@@ -59,24 +72,232 @@ impl File {
         Ok(())
     }
 
-    /// Truncates or extends the underlying file.
+    /// Truncates or extends the underlying file, filling any extension with zeros.
+    ///
+    /// Runs on the runtime's helper thread before Linux 6.9, which added `IORING_OP_FTRUNCATE`.
     pub fn set_len(&self, size: u64) -> crate::IoResult<()> {
-        let file = unsafe { std::fs::File::from_raw_fd(self.0) };
-        file.set_len(size)?;
-        mem::forget(file);
+        let fd = self.fd;
+        let sqe = RawSqe {
+            opcode: IORING_OP_FTRUNCATE,
+            fd,
+            off: size,
+            ..Default::default()
+        };
+        let fallback = || unsafe { libc::ftruncate(fd, size as i64) } as i64;
+        let result = runtime::syscall_or_unblock(IORING_OP_FTRUNCATE, sqe.build(), fallback)?;
+        assert_eq!(result, 0);
+
+        Ok(())
+    }
+
+    /// Allocates or deallocates disk space for the byte range, see [AllocateMode].
+    ///
+    /// Preallocating avoids fragmentation and running out of space midway through writing.
+    pub fn allocate(&self, offset: u64, len: u64, mode: AllocateMode) -> crate::IoResult<()> {
+        let fd = io_uring::types::Fd(self.fd);
+        let mode = match mode {
+            AllocateMode::Extend => 0,
+            AllocateMode::KeepSize => libc::FALLOC_FL_KEEP_SIZE,
+            AllocateMode::PunchHole => libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE,
+            AllocateMode::ZeroRange => libc::FALLOC_FL_ZERO_RANGE,
+        };
+        let sqe = io_uring::opcode::Fallocate::new(fd, len)
+            .offset(offset)
+            .mode(mode)
+            .build();
+        let fallback = || unsafe { libc::fallocate(fd.0, mode, offset as i64, len as i64) } as i64;
+        let opcode = io_uring::opcode::Fallocate::CODE;
+        let result = runtime::syscall_or_unblock(opcode, sqe, fallback)?;
+        assert_eq!(result, 0);
+
+        Ok(())
+    }
+
+    /// Tells the kernel how the byte range will be accessed, so it can tune caching and readahead.
+    ///
+    /// A length of 0 extends the range to the end of the file.
+    pub fn advise(&self, offset: u64, len: u64, advice: Advice) -> crate::IoResult<()> {
+        let fd = io_uring::types::Fd(self.fd);
+        let advice = match advice {
+            Advice::Normal => libc::POSIX_FADV_NORMAL,
+            Advice::Sequential => libc::POSIX_FADV_SEQUENTIAL,
+            Advice::Random => libc::POSIX_FADV_RANDOM,
+            Advice::NoReuse => libc::POSIX_FADV_NOREUSE,
+            Advice::WillNeed => libc::POSIX_FADV_WILLNEED,
+            Advice::DontNeed => libc::POSIX_FADV_DONTNEED,
+        };
+        let sqe = io_uring::opcode::Fadvise::new(fd, len as i64, advice)
+            .offset(offset)
+            .build();
+        let fallback = || unsafe {
+            match libc::posix_fadvise(fd.0, offset as i64, len as i64, advice) {
+                0 => 0,
+                error => {
+                    *libc::__errno_location() = error; // returned rather than set
+                    -1
+                }
+            }
+        };
+        let result = match u32::try_from(len) {
+            Ok(_) => {
+                let opcode = io_uring::opcode::Fadvise::CODE;
+                runtime::syscall_or_unblock(opcode, sqe, fallback)?
+            }
+            Err(_) => unblock(|| errno_result(fallback() as i32))? as u32, // io_uring truncates the length
+        };
+        assert_eq!(result, 0);
 
         Ok(())
     }
 
+    /// Reads into the whole buffer starting at the offset, for files opened with `libc::O_DIRECT`.
+    ///
+    /// The offset has to be a multiple of the buffer's alignment.
+    /// Returns fewer bytes than the buffer's length at the end of the file, or if the buffer is 4 GiB or larger.
+    pub fn read_direct_at(&self, buf: &mut AlignedBuf, offset: u64) -> crate::IoResult<usize> {
+        check_direct_offset(buf, offset)?;
+
+        let fd = io_uring::types::Fd(self.fd);
+        let (pointer, length) = (buf.pointer.as_ptr(), direct_length(buf.len, buf.alignment));
+        let (opcode, sqe) = match buf.register() {
+            Some(index) => {
+                let opcode = io_uring::opcode::ReadFixed::new(fd, pointer, length, index);
+                (
+                    io_uring::opcode::ReadFixed::CODE,
+                    opcode.offset(offset).build(),
+                )
+            }
+            None => {
+                let opcode = io_uring::opcode::Read::new(fd, pointer, length);
+                (io_uring::opcode::Read::CODE, opcode.offset(offset).build())
+            }
+        };
+        let pointer = pointer as usize; // sent to the helper thread
+        let fallback = || unsafe {
+            libc::pread(fd.0, pointer as *mut _, length as usize, offset as i64) as i64
+        };
+        let bytes_read = runtime::syscall_or_unblock(opcode, sqe, fallback)?;
+        Ok(bytes_read as usize)
+    }
+
+    /// Writes the whole buffer starting at the offset, for files opened with `libc::O_DIRECT`.
+    ///
+    /// The offset has to be a multiple of the buffer's alignment.
+    /// Writes fewer bytes than the buffer's length if it's 4 GiB or larger.
+    pub fn write_direct_at(&self, buf: &AlignedBuf, offset: u64) -> crate::IoResult<usize> {
+        check_direct_offset(buf, offset)?;
+
+        let fd = io_uring::types::Fd(self.fd);
+        let (pointer, length) = (buf.pointer.as_ptr(), direct_length(buf.len, buf.alignment));
+        let (opcode, sqe) = match buf.register() {
+            Some(index) => {
+                let opcode = io_uring::opcode::WriteFixed::new(fd, pointer, length, index);
+                (
+                    io_uring::opcode::WriteFixed::CODE,
+                    opcode.offset(offset).build(),
+                )
+            }
+            None => {
+                let opcode = io_uring::opcode::Write::new(fd, pointer, length);
+                (io_uring::opcode::Write::CODE, opcode.offset(offset).build())
+            }
+        };
+        let pointer = pointer as usize; // sent to the helper thread
+        let fallback = || unsafe {
+            libc::pwrite(fd.0, pointer as *const _, length as usize, offset as i64) as i64
+        };
+        let bytes_wrote = runtime::syscall_or_unblock(opcode, sqe, fallback)?;
+        Ok(bytes_wrote as usize)
+    }
+
     /// Queries metadata about the underlying file.
-    pub fn metadata(&self) -> crate::IoResult<std::fs::Metadata> {
-        let file = unsafe { std::fs::File::from_raw_fd(self.0) };
-        let metadata = file.metadata()?;
-        mem::forget(file);
+    pub fn metadata(&self) -> crate::IoResult<Metadata> {
+        statx(self.fd, Default::default(), libc::AT_EMPTY_PATH) // empty path
+    }
+
+    /// Reads bytes starting at the offset, without using or moving the file offset.
+    ///
+    /// Fibers can read from the same file at different offsets concurrently.
+    pub fn read_at(&self, buf: &mut [u8], offset: u64) -> crate::IoResult<usize> {
+        let fd = io_uring::types::Fd(self.fd);
+        let length = cmp::min(buf.len() as u32, READ_LIMIT);
+        let sqe = io_uring::opcode::Read::new(fd, buf.as_mut_ptr(), length)
+            .offset(offset)
+            .build();
+        let fallback = || unsafe {
+            libc::pread(
+                fd.0,
+                buf.as_mut_ptr().cast(),
+                length as usize,
+                offset as i64,
+            ) as i64
+        };
+        let opcode = io_uring::opcode::Read::CODE;
+        let bytes_read = runtime::syscall_or_unblock(opcode, sqe, fallback)?;
+        Ok(bytes_read as usize)
+    }
+
+    /// Writes bytes starting at the offset, without using or moving the file offset.
+    ///
+    /// Files opened in append mode ignore the offset on Linux.
+    pub fn write_at(&self, buf: &[u8], offset: u64) -> crate::IoResult<usize> {
+        let fd = io_uring::types::Fd(self.fd);
+        let length = cmp::min(buf.len() as u32, READ_LIMIT);
+        let sqe = io_uring::opcode::Write::new(fd, buf.as_ptr(), length)
+            .offset(offset)
+            .build();
+        let fallback = || unsafe {
+            libc::pwrite(fd.0, buf.as_ptr().cast(), length as usize, offset as i64) as i64
+        };
+        let opcode = io_uring::opcode::Write::CODE;
+        let bytes_wrote = runtime::syscall_or_unblock(opcode, sqe, fallback)?;
+        Ok(bytes_wrote as usize)
+    }
 
-        // TODO io_uring operation
+    /// Reads exactly enough bytes to fill the buffer, starting at the offset.
+    ///
+    /// Fails with [io::ErrorKind::UnexpectedEof] if the file ends first, leaving the buffer's contents unspecified.
+    pub fn read_exact_at(&self, mut buf: &mut [u8], mut offset: u64) -> crate::IoResult<()> {
+        while !buf.is_empty() {
+            match self.read_at(buf, offset)? {
+                0 => {
+                    let error =
+                        io::Error::new(io::ErrorKind::UnexpectedEof, "failed to fill whole buffer");
+                    return Err(crate::Error::Original(error));
+                }
+                n => {
+                    buf = &mut buf[n..];
+                    offset += n as u64;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes the entire buffer, starting at the offset.
+    pub fn write_all_at(&self, mut buf: &[u8], mut offset: u64) -> crate::IoResult<()> {
+        while !buf.is_empty() {
+            match self.write_at(buf, offset)? {
+                0 => {
+                    let error =
+                        io::Error::new(io::ErrorKind::WriteZero, "failed to write whole buffer");
+                    return Err(crate::Error::Original(error));
+                }
+                n => {
+                    buf = &buf[n..];
+                    offset += n as u64;
+                }
+            }
+        }
+
+        Ok(())
+    }
 
-        Ok(metadata)
+    fn advance(&mut self, bytes: usize) {
+        if let Some(offset) = &mut self.offset {
+            *offset += bytes as u64;
+        }
     }
 
     // /// ...
@@ -84,9 +305,25 @@ impl File {
     //
     // }
 
+    /// Reads an extended attribute of the underlying file, or none if it isn't set.
+    pub fn get_xattr(&self, name: impl AsRef<ffi::OsStr>) -> crate::IoResult<Option<Vec<u8>>> {
+        let name = name_to_cstring(name.as_ref())?;
+        get_xattr_of(XattrTarget::File(self.fd), &name)
+    }
+
+    /// Sets an extended attribute of the underlying file, creating or replacing it.
+    pub fn set_xattr(
+        &self,
+        name: impl AsRef<ffi::OsStr>,
+        value: impl AsRef<[u8]>,
+    ) -> crate::IoResult<()> {
+        let name = name_to_cstring(name.as_ref())?;
+        set_xattr_of(XattrTarget::File(self.fd), &name, value.as_ref())
+    }
+
     /// Changes the permissions on the underlying file.
     pub fn set_permissions(&self, permissions: std::fs::Permissions) -> crate::IoResult<()> {
-        let file = unsafe { std::fs::File::from_raw_fd(self.0) };
+        let file = unsafe { std::fs::File::from_raw_fd(self.fd) };
         file.set_permissions(permissions)?;
         mem::forget(file);
 
@@ -96,22 +333,25 @@ impl File {
 
 impl Drop for File {
     fn drop(&mut self) {
-        let fd = io_uring::types::Fd(self.0);
+        let fd = io_uring::types::Fd(self.fd);
         let sqe = io_uring::opcode::Close::new(fd).build();
-        let _ = runtime::syscall(sqe);
+        let fallback = || unsafe { libc::close(fd.0) } as i64;
+        let opcode = io_uring::opcode::Close::CODE;
+        // don't leak when cancelled
+        let _ = runtime::shield(|| runtime::syscall_or_unblock(opcode, sqe, fallback));
     }
 }
 
 // TODO: doesn't work if using fixed fd
 impl FromRawFd for File {
     unsafe fn from_raw_fd(fd: RawFd) -> Self {
-        File(fd)
+        File { fd, offset: None } // might not support seeking
     }
 }
 
 impl AsRawFd for File {
     fn as_raw_fd(&self) -> RawFd {
-        self.0
+        self.fd
     }
 }
 
@@ -135,15 +375,61 @@ const READ_LIMIT: u32 = libc::c_int::MAX as u32 - 1;
 #[cfg(not(target_os = "macos"))]
 const READ_LIMIT: u32 = libc::ssize_t::MAX as u32;
 
+/// Whether the file can be read and written at an offset, unlike pipes, terminals, and sockets.
+fn is_seekable(fd: RawFd) -> bool {
+    let mut stat = unsafe { mem::zeroed::<libc::stat>() };
+    // doesn't block, the inode is already in memory
+    if unsafe { libc::fstat(fd, &mut stat) } == -1 {
+        return false;
+    }
+
+    matches!(stat.st_mode & libc::S_IFMT, libc::S_IFREG | libc::S_IFBLK)
+}
+
+/// Tells io_uring to use the kernel's file offset, see [File::offset].
+const CURRENT_POSITION: u64 = u64::MAX;
+
 impl io::Write for File {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        let fd = io_uring::types::Fd(self.0);
-        let sqe =
-            io_uring::opcode::Write::new(fd, buf.as_ptr(), cmp::min(buf.len() as u32, READ_LIMIT))
-                .offset(0_u64.wrapping_sub(1)) // use file offset for files that support seeking
-                .build();
-        let bytes_wrote = runtime::syscall(sqe)?;
-        Ok(bytes_wrote as usize)
+        let bytes_wrote = match self.offset {
+            Some(offset) => self.write_at(buf, offset)?,
+            None => {
+                let fd = io_uring::types::Fd(self.fd);
+                let length = cmp::min(buf.len() as u32, READ_LIMIT);
+                let sqe = io_uring::opcode::Write::new(fd, buf.as_ptr(), length)
+                    .offset(CURRENT_POSITION)
+                    .build();
+                let fallback =
+                    || unsafe { libc::write(fd.0, buf.as_ptr().cast(), length as usize) } as i64;
+                let opcode = io_uring::opcode::Write::CODE;
+                runtime::syscall_or_unblock(opcode, sqe, fallback)? as usize
+            }
+        };
+
+        self.advance(bytes_wrote);
+        Ok(bytes_wrote)
+    }
+
+    fn write_vectored(&mut self, bufs: &[io::IoSlice<'_>]) -> io::Result<usize> {
+        let fd = io_uring::types::Fd(self.fd);
+        let offset = self.offset;
+        let length = cmp::min(bufs.len(), libc::UIO_MAXIOV as usize); // more fail with EINVAL
+        let iovecs = bufs.as_ptr().cast::<libc::iovec>(); // same layout on unix
+        let sqe = io_uring::opcode::Writev::new(fd, iovecs, length as u32)
+            .offset(offset.unwrap_or(CURRENT_POSITION))
+            .build();
+        let fallback = || unsafe {
+            let iovecs = bufs.as_ptr().cast();
+            match offset {
+                Some(offset) => libc::pwritev(fd.0, iovecs, length as i32, offset as i64),
+                None => libc::writev(fd.0, iovecs, length as i32),
+            }
+        } as i64;
+        let opcode = io_uring::opcode::Writev::CODE;
+        let bytes_wrote = runtime::syscall_or_unblock(opcode, sqe, fallback)? as usize;
+
+        self.advance(bytes_wrote);
+        Ok(bytes_wrote)
     }
 
     fn flush(&mut self) -> io::Result<()> {
@@ -153,16 +439,77 @@ impl io::Write for File {
 
 impl io::Read for File {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        let fd = io_uring::types::Fd(self.0);
-        let sqe = io_uring::opcode::Read::new(
-            fd,
-            buf.as_mut_ptr(),
-            cmp::min(buf.len() as u32, READ_LIMIT),
-        )
-        .offset(0_u64.wrapping_sub(1)) // use file offset for files that support seeking
-        .build();
-        let bytes_read = runtime::syscall(sqe)?;
-        Ok(bytes_read as usize)
+        let bytes_read = match self.offset {
+            Some(offset) => self.read_at(buf, offset)?,
+            None => {
+                let fd = io_uring::types::Fd(self.fd);
+                let length = cmp::min(buf.len() as u32, READ_LIMIT);
+                let sqe = io_uring::opcode::Read::new(fd, buf.as_mut_ptr(), length)
+                    .offset(CURRENT_POSITION)
+                    .build();
+                let fallback =
+                    || unsafe { libc::read(fd.0, buf.as_mut_ptr().cast(), length as usize) } as i64;
+                let opcode = io_uring::opcode::Read::CODE;
+                runtime::syscall_or_unblock(opcode, sqe, fallback)? as usize
+            }
+        };
+
+        self.advance(bytes_read);
+        Ok(bytes_read)
+    }
+
+    fn read_vectored(&mut self, bufs: &mut [io::IoSliceMut<'_>]) -> io::Result<usize> {
+        let fd = io_uring::types::Fd(self.fd);
+        let offset = self.offset;
+        let length = cmp::min(bufs.len(), libc::UIO_MAXIOV as usize); // more fail with EINVAL
+        let iovecs = bufs.as_mut_ptr().cast::<libc::iovec>(); // same layout on unix
+        let sqe = io_uring::opcode::Readv::new(fd, iovecs, length as u32)
+            .offset(offset.unwrap_or(CURRENT_POSITION))
+            .build();
+        let fallback = || unsafe {
+            let iovecs = bufs.as_ptr().cast();
+            match offset {
+                Some(offset) => libc::preadv(fd.0, iovecs, length as i32, offset as i64),
+                None => libc::readv(fd.0, iovecs, length as i32),
+            }
+        } as i64;
+        let opcode = io_uring::opcode::Readv::CODE;
+        let bytes_read = runtime::syscall_or_unblock(opcode, sqe, fallback)? as usize;
+
+        self.advance(bytes_read);
+        Ok(bytes_read)
+    }
+}
+
+impl io::Seek for File {
+    /// Moves the file offset in userspace, without a syscall unless seeking relative to the end.
+    fn seek(&mut self, position: io::SeekFrom) -> io::Result<u64> {
+        let Some(offset) = self.offset else {
+            let (whence, by) = match position {
+                io::SeekFrom::Start(n) => (libc::SEEK_SET, n as i64),
+                io::SeekFrom::End(n) => (libc::SEEK_END, n),
+                io::SeekFrom::Current(n) => (libc::SEEK_CUR, n),
+            };
+            // doesn't block, it only updates the kernel's file offset
+            let result = unsafe { libc::lseek(self.fd, by, whence) };
+            return match result {
+                -1 => Err(io::Error::last_os_error()),
+                result => Ok(result as u64),
+            };
+        };
+
+        let (base, by) = match position {
+            io::SeekFrom::Start(n) => (n, 0),
+            io::SeekFrom::End(n) => (self.metadata()?.len(), n),
+            io::SeekFrom::Current(n) => (offset, n),
+        };
+        let Some(offset) = base.checked_add_signed(by) else {
+            let message = "invalid seek to a negative or overflowing position";
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+        };
+
+        self.offset = Some(offset);
+        Ok(offset)
     }
 }
 
@@ -254,6 +601,20 @@ impl OpenOptions {
         self
     }
 
+    /// Sets extra flags to pass to `open(2)`, like `libc::O_DIRECT`.
+    ///
+    /// The access mode bits are ignored, since they're set by [OpenOptions::read] and [OpenOptions::write].
+    pub fn custom_flags(&mut self, flags: i32) -> &mut Self {
+        self.custom_flags = flags;
+        self
+    }
+
+    /// Sets the permission bits that a newly created file gets, before the umask is applied.
+    pub fn mode(&mut self, mode: u32) -> &mut Self {
+        self.mode = mode as libc::mode_t;
+        self
+    }
+
     fn get_access_mode(&self) -> io::Result<libc::c_int> {
         match (self.read, self.write, self.append) {
             (true, false, false) => Ok(libc::O_RDONLY),
@@ -292,7 +653,7 @@ impl OpenOptions {
     /// Opens a file at [path] with the options specified by [self].
     pub fn open(&self, path: impl AsRef<Path>) -> crate::IoResult<File> {
         let fd = io_uring::types::Fd(libc::AT_FDCWD); // pathname is relative to working directory
-        let path = ffi::CString::new(path.as_ref().as_os_str().as_bytes()).unwrap();
+        let path = path_to_cstring(path.as_ref())?;
         let flags = libc::O_CLOEXEC
             | self.get_access_mode()?
             | self.get_creation_mode()?
@@ -301,7 +662,26 @@ impl OpenOptions {
             .mode(self.mode)
             .flags(flags)
             .build();
-        runtime::syscall(sqe).map(|fd| File(fd as i32))
+        let fallback = || unsafe { libc::openat(fd.0, path.as_ptr(), flags, self.mode) } as i64;
+        let opcode = io_uring::opcode::OpenAt::CODE;
+        let fd = runtime::syscall_or_unblock(opcode, sqe, fallback)?;
+
+        // appending writes to the end, wherever the userspace offset is
+        let offset = if self.append || !is_seekable(fd as RawFd) {
+            None
+        } else {
+            Some(0)
+        };
+        Ok(File {
+            fd: fd as RawFd,
+            offset,
+        })
+    }
+}
+
+impl Default for OpenOptions {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
@@ -314,15 +694,298 @@ impl OpenOptions {
 ///
 /// On success, the total number of bytes copied is returned and it is equal to the length of the `to` file as reported by `metadata`.
 ///
+/// Shares the data with a reflink if the filesystem supports it, otherwise splices it through a pipe in chunks with io_uring.
+/// Other fibers run between chunks, and a cancelled copy stops early, leaving `to` partially written.
+///
+/// Unlike [std::fs::copy], it doesn't use `copy_file_range`, which would tie up the runtime's helper thread for the whole copy.
+/// That gives up server side copies on network filesystems like NFS, though the data still never passes through userspace.
+///
 /// If you want to copy the contents of one file to another and you’re working with [`File`]s, see the [`io::copy()`] function.
 pub fn copy(from: impl AsRef<Path>, to: impl AsRef<Path>) -> crate::IoResult<u64> {
-    std::fs::copy(from.as_ref(), to.as_ref()).map_err(crate::Error::from_io_error)
+    copy_with_progress(from, to, |_| {})
 }
 
-/// Queries metadata about the underlying file.
-pub fn metadata(path: impl AsRef<Path>) -> crate::IoResult<std::fs::Metadata> {
-    let file = File::open(path.as_ref())?;
-    file.metadata()
+/// Like [copy], calling `progress` with the number of bytes copied so far after every chunk.
+pub fn copy_with_progress(
+    from: impl AsRef<Path>,
+    to: impl AsRef<Path>,
+    mut progress: impl FnMut(u64),
+) -> crate::IoResult<u64> {
+    let reader = File::open(from.as_ref())?;
+    let metadata = reader.metadata()?;
+    if !metadata.is_file() {
+        let message = "the source path is neither a regular file nor a symlink to a regular file";
+        return Err(crate::Error::Original(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            message,
+        )));
+    }
+
+    let mut options = OpenOptions::new();
+    options
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(metadata.mode() & 0o7777);
+    let writer = options.open(to.as_ref())?;
+    writer.set_permissions(metadata.permissions())?; // mode only applies to new files
+
+    // shares extents on copy on write filesystems, like btrfs and xfs
+    let (from, to) = (reader.fd, writer.fd);
+    match unblock(|| errno_result(unsafe { libc::ioctl(to, libc::FICLONE, from) })) {
+        Ok(_) => {
+            progress(metadata.len());
+            return Ok(metadata.len());
+        }
+        Err(crate::Error::Cancelled(reason)) => return Err(crate::Error::Cancelled(reason)),
+        Err(crate::Error::Original(_)) => {} // not supported, or across filesystems
+    }
+
+    crate::io::copy_fds(from, to, &mut progress)
+}
+
+fn errno_result(result: i32) -> io::Result<i32> {
+    match result {
+        -1 => Err(io::Error::last_os_error()),
+        result => Ok(result),
+    }
+}
+
+/// Queries metadata about a file, following symlinks.
+///
+/// Doesn't need permission to read the file, only to search the directories leading to it.
+pub fn metadata(path: impl AsRef<Path>) -> crate::IoResult<Metadata> {
+    let path = path_to_cstring(path.as_ref())?;
+    statx(libc::AT_FDCWD, &path, 0)
+}
+
+/// Queries metadata about a file, without following symlinks.
+pub fn symlink_metadata(path: impl AsRef<Path>) -> crate::IoResult<Metadata> {
+    let path = path_to_cstring(path.as_ref())?;
+    statx(libc::AT_FDCWD, &path, libc::AT_SYMLINK_NOFOLLOW)
+}
+
+/// Whether a path points at an existing file, following symlinks.
+///
+/// Unlike [Path::exists], errors other than the file not existing are returned, like permission being denied.
+pub fn try_exists(path: impl AsRef<Path>) -> crate::IoResult<bool> {
+    match metadata(path) {
+        Ok(_) => Ok(true),
+        Err(crate::Error::Original(error)) if error.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(error) => Err(error),
+    }
+}
+
+/// Queries metadata relative to the directory, with `AT_*` flags.
+fn statx(directory: RawFd, path: &ffi::CStr, flags: i32) -> crate::IoResult<Metadata> {
+    let fd = io_uring::types::Fd(directory);
+    let mask =
+        libc::STATX_BASIC_STATS | libc::STATX_BTIME | libc::STATX_MNT_ID | libc::STATX_DIOALIGN;
+    let mut statx: libc::statx = unsafe { mem::zeroed() };
+    let buffer = &mut statx as *mut libc::statx;
+    let sqe = io_uring::opcode::Statx::new(fd, path.as_ptr(), buffer.cast())
+        .flags(flags)
+        .mask(mask)
+        .build();
+    let fallback = || unsafe { libc::statx(fd.0, path.as_ptr(), flags, mask, &mut statx) } as i64;
+    let opcode = io_uring::opcode::Statx::CODE;
+    let result = runtime::syscall_or_unblock(opcode, sqe, fallback)?;
+    assert_eq!(result, 0);
+
+    Ok(Metadata(statx))
+}
+
+/// Metadata about a file, returned by [metadata], [symlink_metadata] and [File::metadata].
+///
+/// Exposes everything `statx(2)` reports, which is more than [std::fs::Metadata].
+#[derive(Clone, Copy)]
+pub struct Metadata(libc::statx);
+
+impl Metadata {
+    /// Type of the file.
+    pub fn file_type(&self) -> FileType {
+        FileType(self.mode() & libc::S_IFMT)
+    }
+
+    /// Whether it's a directory.
+    pub fn is_dir(&self) -> bool {
+        self.file_type().is_dir()
+    }
+
+    /// Whether it's a regular file.
+    pub fn is_file(&self) -> bool {
+        self.file_type().is_file()
+    }
+
+    /// Whether it's a symbolic link, only possible with [symlink_metadata].
+    pub fn is_symlink(&self) -> bool {
+        self.file_type().is_symlink()
+    }
+
+    /// Size of the file in bytes.
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> u64 {
+        self.0.stx_size
+    }
+
+    /// Permission bits of the file.
+    pub fn permissions(&self) -> std::fs::Permissions {
+        std::fs::Permissions::from_mode(self.mode())
+    }
+
+    /// Last time the file's contents were modified.
+    pub fn modified(&self) -> io::Result<SystemTime> {
+        self.timestamp(libc::STATX_MTIME, self.0.stx_mtime)
+    }
+
+    /// Last time the file was accessed, which filesystems often don't keep track of precisely.
+    pub fn accessed(&self) -> io::Result<SystemTime> {
+        self.timestamp(libc::STATX_ATIME, self.0.stx_atime)
+    }
+
+    /// Time the file was created, which not every filesystem reports.
+    pub fn created(&self) -> io::Result<SystemTime> {
+        self.timestamp(libc::STATX_BTIME, self.0.stx_btime)
+    }
+
+    fn timestamp(&self, field: u32, timestamp: libc::statx_timestamp) -> io::Result<SystemTime> {
+        if self.0.stx_mask & field == 0 {
+            let message = "timestamp isn't available on this filesystem";
+            return Err(io::Error::new(io::ErrorKind::Unsupported, message));
+        }
+
+        let since_epoch = Duration::new(timestamp.tv_sec.unsigned_abs(), timestamp.tv_nsec);
+        Ok(match timestamp.tv_sec >= 0 {
+            true => SystemTime::UNIX_EPOCH + since_epoch,
+            false => SystemTime::UNIX_EPOCH - since_epoch,
+        })
+    }
+
+    /// Identifies the mount that the file is on, if the kernel reports it (since Linux 5.8).
+    pub fn mount_id(&self) -> Option<u64> {
+        (self.0.stx_mask & libc::STATX_MNT_ID != 0).then_some(self.0.stx_mnt_id)
+    }
+
+    /// Alignment that `O_DIRECT` buffers need in memory, if the kernel reports it (since Linux 6.1).
+    ///
+    /// None if the file doesn't support direct I/O at all.
+    pub fn dio_mem_align(&self) -> Option<u32> {
+        let is_reported = self.0.stx_mask & libc::STATX_DIOALIGN != 0;
+        (is_reported && self.0.stx_dio_mem_align != 0).then_some(self.0.stx_dio_mem_align)
+    }
+
+    /// Alignment that `O_DIRECT` offsets and lengths need, if the kernel reports it (since Linux 6.1).
+    ///
+    /// None if the file doesn't support direct I/O at all.
+    pub fn dio_offset_align(&self) -> Option<u32> {
+        let is_reported = self.0.stx_mask & libc::STATX_DIOALIGN != 0;
+        (is_reported && self.0.stx_dio_offset_align != 0).then_some(self.0.stx_dio_offset_align)
+    }
+
+    /// `libc::STATX_ATTR_*` flags set on the file, like `STATX_ATTR_IMMUTABLE`.
+    ///
+    /// Only flags in [Metadata::attributes_mask] are meaningful.
+    pub fn attributes(&self) -> u64 {
+        self.0.stx_attributes
+    }
+
+    /// `libc::STATX_ATTR_*` flags that the filesystem supports.
+    pub fn attributes_mask(&self) -> u64 {
+        self.0.stx_attributes_mask
+    }
+
+    /// ID of the device containing the file.
+    pub fn dev(&self) -> u64 {
+        libc::makedev(self.0.stx_dev_major, self.0.stx_dev_minor)
+    }
+
+    /// Inode number.
+    pub fn ino(&self) -> u64 {
+        self.0.stx_ino
+    }
+
+    /// File type and permission bits.
+    pub fn mode(&self) -> u32 {
+        self.0.stx_mode as u32
+    }
+
+    /// Number of hard links.
+    pub fn nlink(&self) -> u64 {
+        self.0.stx_nlink as u64
+    }
+
+    /// User ID of the owner.
+    pub fn uid(&self) -> u32 {
+        self.0.stx_uid
+    }
+
+    /// Group ID of the owner.
+    pub fn gid(&self) -> u32 {
+        self.0.stx_gid
+    }
+
+    /// Device ID, if it's a special file.
+    pub fn rdev(&self) -> u64 {
+        libc::makedev(self.0.stx_rdev_major, self.0.stx_rdev_minor)
+    }
+
+    /// Size of the file in bytes, same as [Metadata::len].
+    pub fn size(&self) -> u64 {
+        self.0.stx_size
+    }
+
+    /// Last access time in seconds since the epoch.
+    pub fn atime(&self) -> i64 {
+        self.0.stx_atime.tv_sec
+    }
+
+    /// Nanoseconds part of [Metadata::atime].
+    pub fn atime_nsec(&self) -> i64 {
+        self.0.stx_atime.tv_nsec as i64
+    }
+
+    /// Last modification time in seconds since the epoch.
+    pub fn mtime(&self) -> i64 {
+        self.0.stx_mtime.tv_sec
+    }
+
+    /// Nanoseconds part of [Metadata::mtime].
+    pub fn mtime_nsec(&self) -> i64 {
+        self.0.stx_mtime.tv_nsec as i64
+    }
+
+    /// Last status change time in seconds since the epoch.
+    pub fn ctime(&self) -> i64 {
+        self.0.stx_ctime.tv_sec
+    }
+
+    /// Nanoseconds part of [Metadata::ctime].
+    pub fn ctime_nsec(&self) -> i64 {
+        self.0.stx_ctime.tv_nsec as i64
+    }
+
+    /// Preferred block size for efficient I/O.
+    pub fn blksize(&self) -> u64 {
+        self.0.stx_blksize as u64
+    }
+
+    /// Number of 512 byte blocks allocated to the file.
+    pub fn blocks(&self) -> u64 {
+        self.0.stx_blocks
+    }
+}
+
+impl fmt::Debug for Metadata {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Metadata")
+            .field("file_type", &self.file_type())
+            .field("permissions", &self.permissions())
+            .field("len", &self.len())
+            .field("modified", &self.modified())
+            .field("accessed", &self.accessed())
+            .field("created", &self.created())
+            .finish_non_exhaustive()
+    }
 }
 
 /// Read the entire contents of a file into a bytes vector.
@@ -346,9 +1009,11 @@ pub fn read_to_string(path: impl AsRef<Path>) -> crate::IoResult<String> {
 /// Removes a file from the filesystem.
 pub fn remove_file(path: impl AsRef<Path>) -> crate::IoResult<()> {
     let fd = io_uring::types::Fd(libc::AT_FDCWD); // pathname is relative to working directory
-    let path = ffi::CString::new(path.as_ref().as_os_str().as_bytes()).unwrap();
+    let path = path_to_cstring(path.as_ref())?;
     let sqe = io_uring::opcode::UnlinkAt::new(fd, path.as_ptr()).build();
-    let result = runtime::syscall(sqe)?;
+    let fallback = || unsafe { libc::unlinkat(fd.0, path.as_ptr(), 0) } as i64;
+    let opcode = io_uring::opcode::UnlinkAt::CODE;
+    let result = runtime::syscall_or_unblock(opcode, sqe, fallback)?;
     assert_eq!(result, 0);
 
     Ok(())
@@ -363,52 +1028,2495 @@ pub fn write(path: impl AsRef<Path>, contents: impl AsRef<[u8]>) -> crate::IoRes
     Ok(())
 }
 
-// TODO: O_LARGEFILE open64, otherwise EOVERFLOW
-// TODO: https://docs.rs/io-uring/latest/io_uring/opcode/struct.MkDirAt.html
+/// Replaces a file's contents all at once, so that readers and crashes never see it half written.
+///
+/// Writes to an anonymous `O_TMPFILE` in the same directory, syncs it, then links it into place.
+/// Keeps the permissions of the file it replaces, otherwise it's created like [write].
+pub fn write_atomic(path: impl AsRef<Path>, contents: impl AsRef<[u8]>) -> crate::IoResult<()> {
+    let path = path.as_ref();
+    let directory = parent_directory(path);
+    let permissions = match metadata(path) {
+        Ok(metadata) => Some(metadata.permissions()),
+        Err(crate::Error::Original(error)) if error.kind() == io::ErrorKind::NotFound => None,
+        Err(error) => return Err(error),
+    };
+
+    let mut options = OpenOptions::new();
+    options
+        .write(true)
+        .custom_flags(libc::O_TMPFILE)
+        .mode(0o666);
+    let file = options.open(directory)?;
+    if let Some(permissions) = permissions {
+        file.set_permissions(permissions)?;
+    }
+    file.write_all_at(contents.as_ref(), 0)?;
+    file.sync_data()?;
+
+    // linking the descriptor itself needs CAP_DAC_READ_SEARCH
+    let source = ffi::CString::new(format!("/proc/self/fd/{}", file.fd)).unwrap();
+    let target = path_to_cstring(path)?;
+    match link_at(&source, &target, libc::AT_SYMLINK_FOLLOW) {
+        Err(crate::Error::Original(error)) if error.kind() == io::ErrorKind::AlreadyExists => {
+            runtime::shield(|| exchange_into_place(&source, path))?; // don't leave the old contents behind
+        }
+        result => result?,
+    }
 
-#[cfg(test)]
-mod tests {
-    use crate::runtime::start;
+    File::open(directory)?.sync_all() // persists the directory entry
+}
 
-    use super::*;
+/// Links the file next to the path, swaps the two, then deletes the old contents.
+fn exchange_into_place(source: &ffi::CStr, path: &Path) -> crate::IoResult<()> {
+    let temporary = parent_directory(path).join(temporary_name());
+    let temporary_c = path_to_cstring(&temporary)?;
+    let target = path_to_cstring(path)?;
+    link_at(source, &temporary_c, libc::AT_SYMLINK_FOLLOW)?;
+
+    let result = match rename_at(&temporary_c, &target, libc::RENAME_EXCHANGE) {
+        Ok(()) => remove_file(&temporary),
+        // filesystem can't exchange, or the file was deleted in the meantime
+        Err(crate::Error::Original(error))
+            if matches!(error.raw_os_error(), Some(libc::EINVAL | libc::ENOENT)) =>
+        {
+            rename_at(&temporary_c, &target, 0)
+        }
+        Err(error) => Err(error),
+    };
+    if result.is_err() {
+        let _ = remove_file(&temporary);
+    }
 
-    #[test]
-    fn creates_and_deletes_file() {
-        start(|| {
-            let path = format!("/tmp/{}", uuid::Uuid::new_v4());
+    result
+}
 
-            File::create(&path).unwrap();
-            assert!(Path::new(&path).exists());
+/// Creates an anonymous file in [std::env::temp_dir], which is deleted once it's closed.
+///
+/// It never has a name, so nothing is left behind even if the process crashes.
+pub fn tempfile() -> crate::IoResult<File> {
+    tempfile_in(std::env::temp_dir())
+}
 
-            remove_file(&path).unwrap();
-            assert!(!Path::new(&path).exists());
-        })
-        .unwrap();
-    }
+/// Creates an anonymous file in the directory, which is deleted once it's closed.
+pub fn tempfile_in(directory: impl AsRef<Path>) -> crate::IoResult<File> {
+    let mut options = OpenOptions::new();
+    options
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_TMPFILE)
+        .mode(0o600);
+    options.open(directory)
+}
 
-    #[test]
-    fn copies_file() {
-        start(|| {
-            let path = format!("/tmp/{}", uuid::Uuid::new_v4());
+/// Directory that's deleted along with its contents once dropped.
+///
+/// Deleted even if the fiber is cancelled, or if it's dropped after the runtime stops.
+pub struct TempDir {
+    path: Option<PathBuf>, // taken by close
+}
 
-            copy("/etc/hosts", &path).unwrap();
+impl TempDir {
+    /// Creates a directory with a unique name in [std::env::temp_dir].
+    pub fn new() -> crate::IoResult<Self> {
+        TempDir::new_in(std::env::temp_dir())
+    }
 
-            assert_eq!(read("/etc/hosts").unwrap(), read(&path).unwrap());
-        })
-        .unwrap();
+    /// Creates a directory with a unique name in the directory.
+    pub fn new_in(directory: impl AsRef<Path>) -> crate::IoResult<Self> {
+        loop {
+            let path = directory.as_ref().join(temporary_name());
+            match create_dir_with_mode(&path, 0o700) {
+                Ok(()) => return Ok(TempDir { path: Some(path) }),
+                Err(crate::Error::Original(error))
+                    if error.kind() == io::ErrorKind::AlreadyExists =>
+                {
+                    continue
+                }
+                Err(error) => return Err(error),
+            }
+        }
     }
 
-    #[test]
-    fn truncates_file() {
-        start(|| {
-            let path = format!("/tmp/{}", uuid::Uuid::new_v4());
-            write(&path, b"hi").unwrap();
+    /// Path to the directory.
+    pub fn path(&self) -> &Path {
+        self.path.as_deref().unwrap()
+    }
 
-            write(&path, b"hello").unwrap();
+    /// Deletes the directory, unlike dropping which ignores errors.
+    pub fn close(mut self) -> crate::IoResult<()> {
+        let path = self.path.take().unwrap();
+        runtime::shield(|| remove_dir_all(path))
+    }
+}
 
-            assert_eq!(read(&path).unwrap(), b"hello");
-        })
-        .unwrap();
+impl AsRef<Path> for TempDir {
+    fn as_ref(&self) -> &Path {
+        self.path()
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let Some(path) = self.path.take() else {
+            return;
+        };
+
+        if runtime::is_running() {
+            let _ = runtime::shield(|| remove_dir_all(path));
+        } else {
+            let _ = std::fs::remove_dir_all(path);
+        }
+    }
+}
+
+impl fmt::Debug for TempDir {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("TempDir").field(&self.path()).finish()
+    }
+}
+
+/// Hidden name that's unlikely to exist already.
+fn temporary_name() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+    let nanos = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .subsec_nanos();
+    format!(".tmp{:x}{nanos:x}{count:x}", std::process::id())
+}
+
+/// Directory containing the path, which is the working directory for bare names.
+fn parent_directory(path: &Path) -> &Path {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    }
+}
+
+/// Creates a new, empty directory.
+///
+/// Fails if the directory already exists or its parent doesn't, see [create_dir_all].
+pub fn create_dir(path: impl AsRef<Path>) -> crate::IoResult<()> {
+    create_dir_with_mode(path.as_ref(), 0o777) // masked by umask
+}
+
+fn create_dir_with_mode(path: &Path, mode: libc::mode_t) -> crate::IoResult<()> {
+    let fd = io_uring::types::Fd(libc::AT_FDCWD); // pathname is relative to working directory
+    let path = path_to_cstring(path)?;
+    let sqe = io_uring::opcode::MkDirAt::new(fd, path.as_ptr())
+        .mode(mode)
+        .build();
+    let fallback = || unsafe { libc::mkdirat(fd.0, path.as_ptr(), mode) } as i64;
+    let opcode = io_uring::opcode::MkDirAt::CODE;
+    let result = runtime::syscall_or_unblock(opcode, sqe, fallback)?;
+    assert_eq!(result, 0);
+
+    Ok(())
+}
+
+/// Creates a directory and all of its missing parents.
+///
+/// Succeeds if the directory already exists.
+pub fn create_dir_all(path: impl AsRef<Path>) -> crate::IoResult<()> {
+    let path = path.as_ref();
+    if path.as_os_str().is_empty() {
+        return Ok(());
+    }
+
+    match create_dir(path) {
+        Ok(()) => return Ok(()),
+        Err(crate::Error::Original(error)) if error.kind() == io::ErrorKind::NotFound => {}
+        Err(crate::Error::Original(_)) if is_dir(path) => return Ok(()),
+        Err(error) => return Err(error),
+    }
+
+    match path.parent() {
+        Some(parent) => create_dir_all(parent)?,
+        None => {
+            let error = io::Error::other("failed to create whole tree");
+            return Err(crate::Error::Original(error));
+        }
+    }
+
+    match create_dir(path) {
+        Err(crate::Error::Original(_)) if is_dir(path) => Ok(()), // created concurrently
+        result => result,
+    }
+}
+
+/// Removes an empty directory.
+pub fn remove_dir(path: impl AsRef<Path>) -> crate::IoResult<()> {
+    let fd = io_uring::types::Fd(libc::AT_FDCWD); // pathname is relative to working directory
+    let path = path_to_cstring(path.as_ref())?;
+    let sqe = io_uring::opcode::UnlinkAt::new(fd, path.as_ptr())
+        .flags(libc::AT_REMOVEDIR)
+        .build();
+    let fallback = || unsafe { libc::unlinkat(fd.0, path.as_ptr(), libc::AT_REMOVEDIR) } as i64;
+    let opcode = io_uring::opcode::UnlinkAt::CODE;
+    let result = runtime::syscall_or_unblock(opcode, sqe, fallback)?;
+    assert_eq!(result, 0);
+
+    Ok(())
+}
+
+/// Removes a directory after removing all of its contents, without following symlinks.
+///
+/// Runs on the runtime's helper thread, since it's made up of many dependent syscalls.
+pub fn remove_dir_all(path: impl AsRef<Path>) -> crate::IoResult<()> {
+    let path = path.as_ref();
+    unblock(|| std::fs::remove_dir_all(path))
+}
+
+/// Renames a file or directory, replacing `to` if it already exists.
+pub fn rename(from: impl AsRef<Path>, to: impl AsRef<Path>) -> crate::IoResult<()> {
+    let from = path_to_cstring(from.as_ref())?;
+    let to = path_to_cstring(to.as_ref())?;
+    rename_at(&from, &to, 0)
+}
+
+/// Like `renameat2(2)`, with pathnames relative to the working directory.
+fn rename_at(from: &ffi::CStr, to: &ffi::CStr, flags: u32) -> crate::IoResult<()> {
+    let fd = io_uring::types::Fd(libc::AT_FDCWD);
+    let sqe = io_uring::opcode::RenameAt::new(fd, from.as_ptr(), fd, to.as_ptr())
+        .flags(flags)
+        .build();
+    let fallback = || unsafe {
+        let (from, to) = (from.as_ptr(), to.as_ptr());
+        libc::syscall(libc::SYS_renameat2, fd.0, from, fd.0, to, flags)
+    };
+    let opcode = io_uring::opcode::RenameAt::CODE;
+    let result = runtime::syscall_or_unblock(opcode, sqe, fallback)?;
+    assert_eq!(result, 0);
+
+    Ok(())
+}
+
+/// Creates a new hard link to the original file, without following symlinks.
+pub fn hard_link(original: impl AsRef<Path>, link: impl AsRef<Path>) -> crate::IoResult<()> {
+    let original = path_to_cstring(original.as_ref())?;
+    let link = path_to_cstring(link.as_ref())?;
+    link_at(&original, &link, 0)
+}
+
+/// Like `linkat(2)`, with pathnames relative to the working directory.
+fn link_at(original: &ffi::CStr, link: &ffi::CStr, flags: i32) -> crate::IoResult<()> {
+    let fd = io_uring::types::Fd(libc::AT_FDCWD);
+    let sqe = io_uring::opcode::LinkAt::new(fd, original.as_ptr(), fd, link.as_ptr())
+        .flags(flags)
+        .build();
+    let fallback = || unsafe {
+        let (original, link) = (original.as_ptr(), link.as_ptr());
+        libc::linkat(fd.0, original, fd.0, link, flags)
+    } as i64;
+    let opcode = io_uring::opcode::LinkAt::CODE;
+    let result = runtime::syscall_or_unblock(opcode, sqe, fallback)?;
+    assert_eq!(result, 0);
+
+    Ok(())
+}
+
+/// Creates a symbolic link pointing to the original path, which doesn't need to exist.
+pub fn symlink(original: impl AsRef<Path>, link: impl AsRef<Path>) -> crate::IoResult<()> {
+    let fd = io_uring::types::Fd(libc::AT_FDCWD); // link is relative to working directory
+    let original = path_to_cstring(original.as_ref())?;
+    let link = path_to_cstring(link.as_ref())?;
+    let sqe = io_uring::opcode::SymlinkAt::new(fd, original.as_ptr(), link.as_ptr()).build();
+    let fallback = || unsafe { libc::symlinkat(original.as_ptr(), fd.0, link.as_ptr()) } as i64;
+    let opcode = io_uring::opcode::SymlinkAt::CODE;
+    let result = runtime::syscall_or_unblock(opcode, sqe, fallback)?;
+    assert_eq!(result, 0);
+
+    Ok(())
+}
+
+/// Reads the path that a symbolic link points to.
+///
+/// Runs on the runtime's helper thread, since io_uring has no equivalent operation.
+pub fn read_link(path: impl AsRef<Path>) -> crate::IoResult<PathBuf> {
+    let path = path.as_ref();
+    unblock(|| std::fs::read_link(path))
+}
+
+/// Reads an extended attribute of a file, like `user.checksum`, or none if it isn't set.
+///
+/// Follows symbolic links.
+pub fn get_xattr(
+    path: impl AsRef<Path>,
+    name: impl AsRef<ffi::OsStr>,
+) -> crate::IoResult<Option<Vec<u8>>> {
+    let path = path_to_cstring(path.as_ref())?;
+    let name = name_to_cstring(name.as_ref())?;
+    get_xattr_of(XattrTarget::Path(&path), &name)
+}
+
+/// Sets an extended attribute of a file, creating or replacing it.
+///
+/// Follows symbolic links.
+pub fn set_xattr(
+    path: impl AsRef<Path>,
+    name: impl AsRef<ffi::OsStr>,
+    value: impl AsRef<[u8]>,
+) -> crate::IoResult<()> {
+    let path = path_to_cstring(path.as_ref())?;
+    let name = name_to_cstring(name.as_ref())?;
+    set_xattr_of(XattrTarget::Path(&path), &name, value.as_ref())
+}
+
+/// Lists the names of a file's extended attributes.
+///
+/// Follows symbolic links.
+/// Runs on the runtime's helper thread, since io_uring has no equivalent operation.
+pub fn list_xattr(path: impl AsRef<Path>) -> crate::IoResult<Vec<ffi::OsString>> {
+    let path = path_to_cstring(path.as_ref())?;
+    let names = unblock(|| {
+        let mut names = Vec::new();
+        loop {
+            let (pointer, size) = (names.as_mut_ptr() as *mut libc::c_char, names.len());
+            match unsafe { libc::listxattr(path.as_ptr(), pointer, size) } {
+                -1 => match io::Error::last_os_error() {
+                    error if error.raw_os_error() == Some(libc::ERANGE) => names.clear(), // grew in the meantime
+                    error => return Err(error),
+                },
+                length if names.is_empty() && length > 0 => names.resize(length as usize, 0),
+                length => {
+                    names.truncate(length as usize);
+                    return Ok(names);
+                }
+            }
+        }
+    })?;
+
+    // nul terminated names
+    let names = names
+        .split(|&byte| byte == 0)
+        .filter(|name| !name.is_empty());
+    Ok(names
+        .map(|name| ffi::OsStr::from_bytes(name).to_owned())
+        .collect())
+}
+
+/// Removes an extended attribute from a file.
+///
+/// Follows symbolic links.
+/// Runs on the runtime's helper thread, since io_uring has no equivalent operation.
+pub fn remove_xattr(path: impl AsRef<Path>, name: impl AsRef<ffi::OsStr>) -> crate::IoResult<()> {
+    let path = path_to_cstring(path.as_ref())?;
+    let name = name_to_cstring(name.as_ref())?;
+    unblock(|| errno_result(unsafe { libc::removexattr(path.as_ptr(), name.as_ptr()) }))?;
+
+    Ok(())
+}
+
+/// What an extended attribute operation applies to.
+#[derive(Clone, Copy)]
+enum XattrTarget<'a> {
+    File(RawFd),
+    Path(&'a ffi::CStr),
+}
+
+fn get_xattr_of(target: XattrTarget, name: &ffi::CStr) -> crate::IoResult<Option<Vec<u8>>> {
+    let mut value: Vec<u8> = Vec::new();
+    loop {
+        // asks for the size while the buffer is empty
+        let (opcode, sqe) = xattr_sqe(target, false, name, value.as_ptr(), value.len());
+        let pointer = value.as_mut_ptr() as usize; // sent to the helper thread
+        let size = value.len();
+        let fallback = || unsafe {
+            let (name, value) = (name.as_ptr(), pointer as *mut libc::c_void);
+            match target {
+                XattrTarget::File(fd) => libc::fgetxattr(fd, name, value, size),
+                XattrTarget::Path(path) => libc::getxattr(path.as_ptr(), name, value, size),
+            }
+        } as i64;
+
+        match runtime::syscall_or_unblock(opcode, sqe, fallback) {
+            Ok(length) if value.is_empty() && length > 0 => value.resize(length as usize, 0),
+            Ok(length) => {
+                value.truncate(length as usize);
+                return Ok(Some(value));
+            }
+            Err(crate::Error::Original(error)) => match error.raw_os_error() {
+                Some(libc::ENODATA) => return Ok(None),
+                Some(libc::ERANGE) => value.clear(), // grew in the meantime
+                _ => return Err(crate::Error::Original(error)),
+            },
+            Err(error) => return Err(error),
+        }
+    }
+}
+
+fn set_xattr_of(target: XattrTarget, name: &ffi::CStr, value: &[u8]) -> crate::IoResult<()> {
+    let (opcode, sqe) = xattr_sqe(target, true, name, value.as_ptr(), value.len());
+    let fallback = || unsafe {
+        let (name, pointer) = (name.as_ptr(), value.as_ptr().cast());
+        match target {
+            XattrTarget::File(fd) => libc::fsetxattr(fd, name, pointer, value.len(), 0),
+            XattrTarget::Path(path) => libc::setxattr(path.as_ptr(), name, pointer, value.len(), 0),
+        }
+    } as i64;
+    let result = runtime::syscall_or_unblock(opcode, sqe, fallback)?;
+    assert_eq!(result, 0);
+
+    Ok(())
+}
+
+// io_uring opcodes for extended attributes, since Linux 5.19
+const IORING_OP_FSETXATTR: u8 = 41;
+const IORING_OP_SETXATTR: u8 = 42;
+const IORING_OP_FGETXATTR: u8 = 43;
+const IORING_OP_GETXATTR: u8 = 44;
+
+/// `IORING_OP_FTRUNCATE`, since Linux 6.9.
+const IORING_OP_FTRUNCATE: u8 = 55;
+
+/// Layout of `struct io_uring_sqe`, for opcodes that the io-uring crate has no builders for.
+#[repr(C)]
+#[derive(Default)]
+struct RawSqe {
+    opcode: u8,
+    flags: u8,
+    ioprio: u16,
+    fd: i32,
+    off: u64, // also addr2
+    addr: u64,
+    len: u32,
+    op_flags: u32, // e.g. xattr_flags
+    user_data: u64,
+    buf_index: u16,
+    personality: u16,
+    file_index: u32,
+    addr3: u64,
+    pad: u64,
+}
+
+impl RawSqe {
+    fn build(self) -> io_uring::squeue::Entry {
+        unsafe { mem::transmute::<RawSqe, io_uring::squeue::Entry>(self) }
+    }
+}
+
+/// Builds a submission that reads the attribute into the value, or sets it from the value.
+fn xattr_sqe(
+    target: XattrTarget,
+    is_set: bool,
+    name: &ffi::CStr,
+    value: *const u8,
+    len: usize,
+) -> (u8, io_uring::squeue::Entry) {
+    let (opcode, fd, path) = match (target, is_set) {
+        (XattrTarget::File(fd), false) => (IORING_OP_FGETXATTR, fd, ptr::null()),
+        (XattrTarget::File(fd), true) => (IORING_OP_FSETXATTR, fd, ptr::null()),
+        (XattrTarget::Path(path), false) => (IORING_OP_GETXATTR, 0, path.as_ptr()),
+        (XattrTarget::Path(path), true) => (IORING_OP_SETXATTR, 0, path.as_ptr()),
+    };
+    let sqe = RawSqe {
+        opcode,
+        fd,
+        off: value as u64,
+        addr: name.as_ptr() as u64,
+        len: u32::try_from(len).unwrap_or(u32::MAX), // too big either way
+        addr3: path as u64,
+        ..Default::default()
+    };
+
+    (opcode, sqe.build())
+}
+
+/// Returns an iterator over the entries within a directory, excluding `.` and `..`.
+///
+/// Entries are read in batches with `getdents64` on the runtime's helper thread, since io_uring has no equivalent operation.
+/// Iteration stops early once the fiber is cancelled.
+pub fn read_dir(path: impl AsRef<Path>) -> crate::IoResult<ReadDir> {
+    let path = path.as_ref();
+    let mut options = OpenOptions::new();
+    options.read(true).custom_flags(libc::O_DIRECTORY);
+    let directory = options.open(path)?;
+
+    Ok(ReadDir {
+        directory,
+        path: Arc::from(path),
+        buffer: vec![0; DIRENT_BUFFER_CAPACITY],
+        position: 0,
+        filled: 0,
+        is_done: false,
+    })
+}
+
+/// Enough for hundreds of entries per batch.
+const DIRENT_BUFFER_CAPACITY: usize = 32 * 1024;
+
+/// Iterator over the entries in a directory, returned by [read_dir].
+pub struct ReadDir {
+    directory: File,
+    path: Arc<Path>,
+    buffer: Vec<u8>,
+    position: usize, // next unread entry within the buffer
+    filled: usize,
+    is_done: bool,
+}
+
+impl ReadDir {
+    fn fill_buffer(&mut self) -> crate::IoResult<()> {
+        let fd = self.directory.fd;
+        let buffer = &mut self.buffer;
+        let result = unblock(|| {
+            let result = unsafe {
+                libc::syscall(libc::SYS_getdents64, fd, buffer.as_mut_ptr(), buffer.len())
+            };
+            match result {
+                -1 => Err(io::Error::last_os_error()),
+                result => Ok(result as usize),
+            }
+        })?;
+
+        self.position = 0;
+        self.filled = result;
+        Ok(())
+    }
+}
+
+impl Iterator for ReadDir {
+    type Item = crate::IoResult<DirEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.is_done {
+                return None;
+            }
+
+            if runtime::is_cancelled() {
+                self.is_done = true;
+                return Some(Err(runtime::cancelled()));
+            }
+
+            if self.position == self.filled {
+                if let Err(error) = self.fill_buffer() {
+                    self.is_done = true;
+                    return Some(Err(error));
+                }
+                if self.filled == 0 {
+                    self.is_done = true;
+                    return None;
+                }
+            }
+
+            // struct linux_dirent64 { d_ino: u64, d_off: i64, d_reclen: u16, d_type: u8, d_name: [u8] }
+            let entry = &self.buffer[self.position..self.filled];
+            let inode = u64::from_ne_bytes(entry[0..8].try_into().unwrap());
+            let length = u16::from_ne_bytes(entry[16..18].try_into().unwrap()) as usize;
+            let kind = entry[18];
+            let name = ffi::CStr::from_bytes_until_nul(&entry[19..length]).unwrap();
+            self.position += length;
+
+            if matches!(name.to_bytes(), b"." | b"..") {
+                continue;
+            }
+
+            return Some(Ok(DirEntry {
+                directory: self.path.clone(),
+                name: ffi::OsStr::from_bytes(name.to_bytes()).to_os_string(),
+                inode,
+                file_type: match kind {
+                    libc::DT_UNKNOWN => None, // not every filesystem reports it
+                    kind => Some(FileType((kind as libc::mode_t) << 12)), // DTTOIF
+                },
+            }));
+        }
+    }
+}
+
+/// Entry returned by the [ReadDir] iterator.
+#[derive(Debug)]
+pub struct DirEntry {
+    directory: Arc<Path>,
+    name: ffi::OsString,
+    inode: u64,
+    file_type: Option<FileType>,
+}
+
+impl DirEntry {
+    /// Full path to the entry, joined onto the path passed to [read_dir].
+    pub fn path(&self) -> PathBuf {
+        self.directory.join(&self.name)
+    }
+
+    /// Bare name of the entry, without any leading path.
+    pub fn file_name(&self) -> ffi::OsString {
+        self.name.clone()
+    }
+
+    /// Inode number of the entry.
+    pub fn ino(&self) -> u64 {
+        self.inode
+    }
+
+    /// Type of the entry, without following symlinks.
+    ///
+    /// Usually known from reading the directory, otherwise it's fetched like [DirEntry::metadata].
+    pub fn file_type(&self) -> crate::IoResult<FileType> {
+        match self.file_type {
+            Some(file_type) => Ok(file_type),
+            None => Ok(self.metadata()?.file_type()),
+        }
+    }
+
+    /// Fetches metadata about the entry, without following symlinks.
+    pub fn metadata(&self) -> crate::IoResult<Metadata> {
+        symlink_metadata(self.path())
+    }
+}
+
+/// Watches a file or directory for changes, like [WatchMask::CREATE].
+///
+/// Directories report changes to their direct children, or all descendants with [WatchMask::RECURSIVE].
+/// Backed by inotify, so changes made through other mounts of the filesystem, like NFS, aren't reported.
+pub fn watch(path: impl AsRef<Path>, mask: WatchMask) -> crate::IoResult<Watcher> {
+    let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC) };
+    if fd == -1 {
+        return Err(crate::Error::from_io_error(io::Error::last_os_error()));
+    }
+    let inotify = unsafe { File::from_raw_fd(fd) };
+
+    let root = path.as_ref().to_path_buf();
+    let events = mask.kernel_events();
+    let is_recursive = mask.is_recursive;
+    let (watches, _) = {
+        let root = root.clone();
+        unblock(move || add_watches(fd, root, events, is_recursive, true))?
+    };
+
+    Ok(Watcher {
+        inotify,
+        root_wd: watches[0].0,
+        mask,
+        directories: watches.into_iter().collect(),
+        buffer: vec![0; INOTIFY_BUFFER_CAPACITY],
+        position: 0,
+        filled: 0,
+        pending: VecDeque::new(),
+        is_done: false,
+        root,
+    })
+}
+
+/// Adds a watch for the path, then for every directory beneath it if recursive.
+///
+/// Returns the watches, with the path's first, and the paths found beneath it.
+/// Directories that disappear midway are skipped, except for the path itself.
+fn add_watches(
+    inotify: RawFd,
+    path: PathBuf,
+    events: u32,
+    is_recursive: bool,
+    is_root: bool,
+) -> io::Result<(Vec<Watch>, Vec<PathBuf>)> {
+    let mut watches = Vec::new();
+    let mut found = Vec::new();
+    let mut directories = vec![path];
+
+    while let Some(directory) = directories.pop() {
+        let is_first = watches.is_empty() && is_root;
+        let flags = if is_first {
+            events
+        } else {
+            events | libc::IN_ONLYDIR | libc::IN_DONT_FOLLOW // symlinks aren't descendants
+        };
+        let path = ffi::CString::new(directory.as_os_str().as_bytes())?;
+        let wd = unsafe { libc::inotify_add_watch(inotify, path.as_ptr(), flags) };
+        if wd == -1 {
+            let error = io::Error::last_os_error();
+            match error.raw_os_error() {
+                Some(libc::ENOENT | libc::ENOTDIR) if !is_first => continue,
+                _ => return Err(error),
+            }
+        }
+        watches.push((wd, directory.clone()));
+
+        if is_recursive && (!is_first || std::fs::metadata(&directory)?.is_dir()) {
+            // listed after adding the watch, so new children show up in one or the other
+            let Ok(entries) = std::fs::read_dir(&directory) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                if entry.file_type().is_ok_and(|file_type| file_type.is_dir()) {
+                    directories.push(entry.path());
+                }
+                found.push(entry.path());
+            }
+        }
+    }
+
+    Ok((watches, found))
+}
+
+/// Watch descriptor and the path it watches.
+type Watch = (i32, PathBuf);
+
+/// Enough for hundreds of events per batch, and at least one with the longest name.
+const INOTIFY_BUFFER_CAPACITY: usize = 16 * 1024;
+
+/// Kinds of changes for [watch] to report.
+///
+/// Combined with `|`, e.g. `WatchMask::CREATE | WatchMask::DELETE`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchMask {
+    events: u32, // IN_* flags
+    is_recursive: bool,
+}
+
+impl WatchMask {
+    /// Reports [Event::Created].
+    pub const CREATE: WatchMask = WatchMask::new(libc::IN_CREATE);
+    /// Reports [Event::Modified].
+    pub const MODIFY: WatchMask = WatchMask::new(libc::IN_MODIFY);
+    /// Reports [Event::Written].
+    pub const CLOSE_WRITE: WatchMask = WatchMask::new(libc::IN_CLOSE_WRITE);
+    /// Reports [Event::AttributesChanged].
+    pub const ATTRIB: WatchMask = WatchMask::new(libc::IN_ATTRIB);
+    /// Reports [Event::Moved].
+    pub const MOVE: WatchMask =
+        WatchMask::new(libc::IN_MOVED_FROM | libc::IN_MOVED_TO | libc::IN_MOVE_SELF);
+    /// Reports [Event::Deleted].
+    pub const DELETE: WatchMask = WatchMask::new(libc::IN_DELETE | libc::IN_DELETE_SELF);
+    /// Reports every kind of event.
+    pub const ALL: WatchMask = WatchMask::new(
+        WatchMask::CREATE.events
+            | WatchMask::MODIFY.events
+            | WatchMask::CLOSE_WRITE.events
+            | WatchMask::ATTRIB.events
+            | WatchMask::MOVE.events
+            | WatchMask::DELETE.events,
+    );
+    /// Watches every directory beneath the watched directory, including ones created later.
+    pub const RECURSIVE: WatchMask = WatchMask {
+        events: 0,
+        is_recursive: true,
+    };
+
+    const fn new(events: u32) -> Self {
+        WatchMask {
+            events,
+            is_recursive: false,
+        }
+    }
+
+    /// Flags for `inotify_add_watch(2)`, which need new directories in order to watch them.
+    fn kernel_events(&self) -> u32 {
+        match self.is_recursive {
+            true => self.events | libc::IN_CREATE | libc::IN_MOVED_FROM | libc::IN_MOVED_TO,
+            false => self.events,
+        }
+    }
+}
+
+impl ops::BitOr for WatchMask {
+    type Output = WatchMask;
+
+    fn bitor(self, other: WatchMask) -> WatchMask {
+        WatchMask {
+            events: self.events | other.events,
+            is_recursive: self.is_recursive || other.is_recursive,
+        }
+    }
+}
+
+impl ops::BitOrAssign for WatchMask {
+    fn bitor_assign(&mut self, other: WatchMask) {
+        *self = *self | other;
+    }
+}
+
+/// Change reported by a [Watcher].
+///
+/// Paths are joined onto the path passed to [watch].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    /// File or directory was created.
+    ///
+    /// When watching recursively, the contents of a new directory are reported as created too.
+    /// They might be reported twice if they're created while the directory is being watched.
+    Created(PathBuf),
+    /// File was written to.
+    Modified(PathBuf),
+    /// File that was opened for writing was closed, which usually means that it's done changing.
+    Written(PathBuf),
+    /// Permissions, timestamps, ownership, extended attributes, or link count changed.
+    AttributesChanged(PathBuf),
+    /// File or directory was renamed.
+    ///
+    /// Either side is none when it's outside the watched directory, or when the kernel reported the halves separately.
+    Moved {
+        from: Option<PathBuf>,
+        to: Option<PathBuf>,
+    },
+    /// File or directory was deleted.
+    Deleted(PathBuf),
+    /// Kernel's event queue overflowed, so events were lost since the previous one.
+    ///
+    /// Anything derived from the watched files should be rebuilt from scratch.
+    Overflowed,
+}
+
+/// Iterator over changes to watched files, returned by [watch].
+///
+/// Blocks the fiber until the next change.
+/// Ends once the watched path is deleted, or with an error once the fiber is cancelled.
+pub struct Watcher {
+    inotify: File,
+    root: PathBuf,
+    root_wd: i32,
+    mask: WatchMask,
+    directories: HashMap<i32, PathBuf>, // watched paths by watch descriptor
+    buffer: Vec<u8>,
+    position: usize, // next unread event within the buffer
+    filled: usize,
+    pending: VecDeque<Event>,
+    is_done: bool,
+}
+
+impl Watcher {
+    fn fill_buffer(&mut self) -> crate::IoResult<()> {
+        let fd = io_uring::types::Fd(self.inotify.fd);
+        let buffer = &mut self.buffer;
+        let sqe = io_uring::opcode::Read::new(fd, buffer.as_mut_ptr(), buffer.len() as u32).build();
+        self.filled = runtime::syscall(sqe)? as usize;
+        self.position = 0;
+
+        Ok(())
+    }
+
+    /// Parses the event at the position, if the buffer has one.
+    fn parse_event(&self, position: usize) -> Option<(RawEvent, usize)> {
+        // struct inotify_event { wd: i32, mask: u32, cookie: u32, len: u32, name: [u8] }
+        let event = self.buffer.get(position..self.filled)?;
+        if event.len() < 16 {
+            return None;
+        }
+        let wd = i32::from_ne_bytes(event[0..4].try_into().unwrap());
+        let mask = u32::from_ne_bytes(event[4..8].try_into().unwrap());
+        let cookie = u32::from_ne_bytes(event[8..12].try_into().unwrap());
+        let length = u32::from_ne_bytes(event[12..16].try_into().unwrap()) as usize;
+        let name = &event[16..16 + length];
+        let name = match ffi::CStr::from_bytes_until_nul(name) {
+            Ok(name) => name.to_bytes(), // padded with nul bytes
+            Err(_) => name,
+        };
+        let name = ffi::OsStr::from_bytes(name).to_owned();
+
+        let event = RawEvent {
+            wd,
+            mask,
+            cookie,
+            name,
+        };
+        Some((event, position + 16 + length))
+    }
+
+    fn path(&self, event: &RawEvent) -> Option<PathBuf> {
+        let directory = self.directories.get(&event.wd)?;
+        match event.name.is_empty() {
+            true => Some(directory.clone()),
+            false => Some(directory.join(&event.name)),
+        }
+    }
+
+    /// Turns the event into ones to return, while keeping recursive watches up to date.
+    fn handle(&mut self, event: RawEvent) -> crate::IoResult<()> {
+        if event.mask & libc::IN_Q_OVERFLOW != 0 {
+            self.pending.push_back(Event::Overflowed);
+            if self.mask.is_recursive {
+                // new directories might've been missed
+                self.watch_descendants(self.root.clone(), false)?;
+            }
+            return Ok(());
+        }
+
+        if event.mask & libc::IN_IGNORED != 0 {
+            self.directories.remove(&event.wd);
+            return Ok(());
+        }
+
+        // descendants are reported by their parent directory instead
+        let is_self = event.mask & (libc::IN_DELETE_SELF | libc::IN_MOVE_SELF) != 0;
+        if is_self && event.wd != self.root_wd {
+            return Ok(());
+        }
+
+        let Some(path) = self.path(&event) else {
+            return Ok(()); // already removed
+        };
+        let is_dir = event.mask & libc::IN_ISDIR != 0;
+        let is_wanted = event.mask & self.mask.events != 0;
+
+        if event.mask & libc::IN_MOVED_FROM != 0 {
+            let pair = self.parse_event(self.position).filter(|(next, _)| {
+                next.mask & libc::IN_MOVED_TO != 0 && next.cookie == event.cookie
+            });
+            let to = match pair {
+                Some((next, position)) => {
+                    self.position = position;
+                    self.path(&next)
+                }
+                None => None,
+            };
+
+            if self.mask.is_recursive && is_dir {
+                self.move_descendants(&path, to.as_deref());
+            }
+            if self.mask.events & (libc::IN_MOVED_FROM | libc::IN_MOVED_TO) != 0 {
+                self.pending.push_back(Event::Moved {
+                    from: Some(path),
+                    to,
+                });
+            }
+        } else if event.mask & libc::IN_MOVED_TO != 0 {
+            if self.mask.is_recursive && is_dir {
+                self.watch_descendants(path.clone(), false)?;
+            }
+            if is_wanted {
+                let to = Some(path);
+                self.pending.push_back(Event::Moved { from: None, to });
+            }
+        } else if event.mask & libc::IN_CREATE != 0 {
+            if is_wanted {
+                self.pending.push_back(Event::Created(path.clone()));
+            }
+            if self.mask.is_recursive && is_dir {
+                self.watch_descendants(path, is_wanted)?;
+            }
+        } else if is_wanted {
+            let event = match event.mask {
+                mask if mask & libc::IN_MODIFY != 0 => Event::Modified(path),
+                mask if mask & libc::IN_CLOSE_WRITE != 0 => Event::Written(path),
+                mask if mask & libc::IN_ATTRIB != 0 => Event::AttributesChanged(path),
+                mask if mask & libc::IN_MOVE_SELF != 0 => Event::Moved {
+                    from: Some(path),
+                    to: None,
+                },
+                _ => Event::Deleted(path), // IN_DELETE or IN_DELETE_SELF
+            };
+            self.pending.push_back(event);
+        }
+
+        Ok(())
+    }
+
+    /// Watches the directory and everything beneath it, optionally reporting what's already there as created.
+    fn watch_descendants(&mut self, directory: PathBuf, is_reported: bool) -> crate::IoResult<()> {
+        let fd = self.inotify.fd;
+        let events = self.mask.kernel_events();
+        let is_root = directory == self.root;
+        let (watches, found) = unblock(move || add_watches(fd, directory, events, true, is_root))?;
+
+        // adding an existing watch returns the same descriptor
+        self.directories.extend(watches);
+        if is_reported {
+            self.pending.extend(found.into_iter().map(Event::Created));
+        }
+
+        Ok(())
+    }
+
+    /// Follows a directory that was renamed, or stops watching it if it left the watched directory.
+    fn move_descendants(&mut self, from: &Path, to: Option<&Path>) {
+        self.directories.retain(|&wd, path| {
+            let Ok(suffix) = path.strip_prefix(from) else {
+                return true;
+            };
+            match to {
+                Some(to) => {
+                    *path = to.join(suffix);
+                    true
+                }
+                None => {
+                    unsafe { libc::inotify_rm_watch(self.inotify.fd, wd) };
+                    false
+                }
+            }
+        });
+    }
+}
+
+impl Iterator for Watcher {
+    type Item = crate::IoResult<Event>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(event) = self.pending.pop_front() {
+                return Some(Ok(event));
+            }
+
+            if self.is_done || self.directories.is_empty() {
+                return None;
+            }
+
+            if runtime::is_cancelled() {
+                self.is_done = true;
+                return Some(Err(runtime::cancelled()));
+            }
+
+            let event = match self.parse_event(self.position) {
+                Some((event, position)) => {
+                    self.position = position;
+                    event
+                }
+                None => {
+                    if let Err(error) = self.fill_buffer() {
+                        self.is_done = true;
+                        return Some(Err(error));
+                    }
+                    continue;
+                }
+            };
+
+            if let Err(error) = self.handle(event) {
+                self.is_done = true;
+                return Some(Err(error));
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Watcher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Watcher")
+            .field("root", &self.root)
+            .field("mask", &self.mask)
+            .finish_non_exhaustive()
+    }
+}
+
+/// `struct inotify_event` copied out of the buffer.
+struct RawEvent {
+    wd: i32,
+    mask: u32,
+    cookie: u32,
+    name: ffi::OsString,
+}
+
+/// Heap buffer aligned for direct I/O, see [File::read_direct_at].
+///
+/// Registered with io_uring the first time it's used, so the kernel doesn't map its pages for every operation.
+pub struct AlignedBuf {
+    pointer: ptr::NonNull<u8>,
+    len: usize,
+    alignment: usize,
+    registration: Cell<Option<runtime::Registration>>,
+}
+
+impl AlignedBuf {
+    /// Allocates a zeroed buffer for direct I/O on the file, with its length rounded up to the alignment.
+    ///
+    /// The alignment comes from [Metadata::dio_mem_align] and [Metadata::dio_offset_align].
+    /// Defaults to 4096 bytes if the kernel doesn't report them, which suits most devices.
+    pub fn new(file: &File, len: usize) -> crate::IoResult<Self> {
+        let metadata = file.metadata()?;
+        let alignment = match (metadata.dio_mem_align(), metadata.dio_offset_align()) {
+            (Some(memory), Some(offset)) => cmp::max(memory, offset) as usize,
+            _ => 4096,
+        };
+
+        Ok(AlignedBuf::with_alignment(len, alignment))
+    }
+
+    /// Allocates a zeroed buffer with the alignment, with its length rounded up to the alignment.
+    ///
+    /// Panics if the alignment isn't a power of two.
+    pub fn with_alignment(len: usize, alignment: usize) -> Self {
+        let len = cmp::max(len.next_multiple_of(alignment), alignment);
+        let layout = alloc::Layout::from_size_align(len, alignment).unwrap();
+        let pointer = unsafe { alloc::alloc_zeroed(layout) };
+        let Some(pointer) = ptr::NonNull::new(pointer) else {
+            alloc::handle_alloc_error(layout);
+        };
+
+        AlignedBuf {
+            pointer,
+            len,
+            alignment,
+            registration: Cell::new(None),
+        }
+    }
+
+    /// Alignment of the buffer's address, length, and the offsets it can be used at.
+    pub fn alignment(&self) -> usize {
+        self.alignment
+    }
+
+    /// Fixed buffer index, if it could be registered.
+    fn register(&self) -> Option<u16> {
+        runtime::register_buffer(&self.registration, self.pointer.as_ptr(), self.len)
+    }
+}
+
+impl ops::Deref for AlignedBuf {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.pointer.as_ptr(), self.len) }
+    }
+}
+
+impl ops::DerefMut for AlignedBuf {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        unsafe { slice::from_raw_parts_mut(self.pointer.as_ptr(), self.len) }
+    }
+}
+
+impl Drop for AlignedBuf {
+    fn drop(&mut self) {
+        if let Some(registration) = self.registration.get() {
+            runtime::unregister_buffer(registration);
+        }
+
+        let layout = alloc::Layout::from_size_align(self.len, self.alignment).unwrap();
+        unsafe { alloc::dealloc(self.pointer.as_ptr(), layout) };
+    }
+}
+
+impl fmt::Debug for AlignedBuf {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AlignedBuf")
+            .field("len", &self.len)
+            .field("alignment", &self.alignment)
+            .finish_non_exhaustive()
+    }
+}
+
+fn check_direct_offset(buf: &AlignedBuf, offset: u64) -> crate::IoResult<()> {
+    if offset % buf.alignment as u64 != 0 {
+        let message = "offset isn't a multiple of the buffer's alignment";
+        return Err(crate::Error::Original(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            message,
+        )));
+    }
+
+    Ok(())
+}
+
+/// Caps the length of a single direct read or write, keeping it a multiple of the alignment.
+fn direct_length(len: usize, alignment: usize) -> u32 {
+    let limit = READ_LIMIT as usize / alignment * alignment;
+    cmp::min(len, limit) as u32
+}
+
+/// How [File::allocate] changes the file's disk space.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AllocateMode {
+    /// Allocates the range, growing the file if the range goes past its end.
+    Extend,
+    /// Allocates the range without changing the file's size, e.g. to preallocate a log segment.
+    KeepSize,
+    /// Deallocates the range, which then reads as zeros, without changing the file's size.
+    PunchHole,
+    /// Zeroes the range, growing the file if the range goes past its end.
+    ZeroRange,
+}
+
+/// Expected access pattern for [File::advise], like `POSIX_FADV_*`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Advice {
+    /// No particular pattern, the default.
+    Normal,
+    /// Accessed in order, so readahead should be more aggressive.
+    Sequential,
+    /// Accessed in random order, so readahead should be disabled.
+    Random,
+    /// Accessed only once.
+    NoReuse,
+    /// Accessed soon, so it should be read into the page cache now.
+    WillNeed,
+    /// Won't be accessed soon, so it can be dropped from the page cache.
+    DontNeed,
+}
+
+/// Type of a file, like [std::fs::FileType].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FileType(libc::mode_t); // S_IFMT bits of st_mode
+
+impl FileType {
+    /// Whether it's a directory.
+    pub fn is_dir(&self) -> bool {
+        self.0 == libc::S_IFDIR
+    }
+
+    /// Whether it's a regular file.
+    pub fn is_file(&self) -> bool {
+        self.0 == libc::S_IFREG
+    }
+
+    /// Whether it's a symbolic link.
+    pub fn is_symlink(&self) -> bool {
+        self.0 == libc::S_IFLNK
+    }
+
+    /// Whether it's a block device.
+    pub fn is_block_device(&self) -> bool {
+        self.0 == libc::S_IFBLK
+    }
+
+    /// Whether it's a character device.
+    pub fn is_char_device(&self) -> bool {
+        self.0 == libc::S_IFCHR
+    }
+
+    /// Whether it's a named pipe.
+    pub fn is_fifo(&self) -> bool {
+        self.0 == libc::S_IFIFO
+    }
+
+    /// Whether it's a unix domain socket.
+    pub fn is_socket(&self) -> bool {
+        self.0 == libc::S_IFSOCK
+    }
+}
+
+/// Runs a blocking filesystem operation on the runtime's helper thread, unless already cancelled.
+fn unblock<T: Send>(f: impl FnOnce() -> io::Result<T> + Send) -> crate::IoResult<T> {
+    if runtime::is_cancelled() {
+        return Err(runtime::cancelled());
+    }
+
+    runtime::unblock(f).map_err(crate::Error::Original)
+}
+
+fn is_dir(path: &Path) -> bool {
+    metadata(path).is_ok_and(|metadata| metadata.is_dir())
+}
+
+/// Attribute names can't contain nul bytes either.
+fn name_to_cstring(name: &ffi::OsStr) -> crate::IoResult<ffi::CString> {
+    ffi::CString::new(name.as_bytes()).map_err(|_| {
+        let error = io::Error::new(io::ErrorKind::InvalidInput, "name contains a nul byte");
+        crate::Error::Original(error)
+    })
+}
+
+/// Paths can't contain nul bytes, like [std::fs].
+fn path_to_cstring(path: &Path) -> crate::IoResult<ffi::CString> {
+    ffi::CString::new(path.as_os_str().as_bytes()).map_err(|_| {
+        let error = io::Error::new(io::ErrorKind::InvalidInput, "path contains a nul byte");
+        crate::Error::Original(error)
+    })
+}
+
+// TODO: O_LARGEFILE open64, otherwise EOVERFLOW
+
+#[cfg(test)]
+mod tests {
+    use crate::runtime::start;
+
+    use super::*;
+
+    #[test]
+    fn creates_and_deletes_file() {
+        start(|| {
+            let path = format!("/tmp/{}", uuid::Uuid::new_v4());
+
+            File::create(&path).unwrap();
+            assert!(Path::new(&path).exists());
+
+            remove_file(&path).unwrap();
+            assert!(!Path::new(&path).exists());
+        })
+        .unwrap();
+    }
+
+    mod positional {
+        use crate::runtime::spawn;
+
+        use super::*;
+
+        #[test]
+        fn writes_and_reads_at_offsets() {
+            start(|| {
+                let path = format!("/tmp/{}", uuid::Uuid::new_v4());
+                let file = File::options()
+                    .read(true)
+                    .write(true)
+                    .create(true)
+                    .open(&path)
+                    .unwrap();
+
+                file.write_all_at(b"world", 6).unwrap();
+                file.write_all_at(b"hello ", 0).unwrap();
+
+                let mut buffer = [0; 5];
+                file.read_exact_at(&mut buffer, 6).unwrap();
+                assert_eq!(&buffer, b"world");
+                assert_eq!(read(&path).unwrap(), b"hello world");
+            })
+            .unwrap();
+        }
+
+        #[test]
+        fn doesnt_move_file_offset() {
+            start(|| {
+                let mut file = File::open("/etc/hosts").unwrap();
+
+                let mut buffer = [0; 4];
+                file.read_exact_at(&mut buffer, 1).unwrap();
+
+                let mut contents = vec![];
+                file.read_to_end(&mut contents).unwrap();
+                assert_eq!(contents, read("/etc/hosts").unwrap());
+            })
+            .unwrap();
+        }
+
+        #[test]
+        fn reads_concurrently() {
+            start(|| {
+                let path = format!("/tmp/{}", uuid::Uuid::new_v4());
+                let pages: Vec<u8> = (0..8).flat_map(|page| [page; 4096]).collect();
+                write(&path, &pages).unwrap();
+                let file = std::rc::Rc::new(File::open(&path).unwrap());
+
+                let handles: Vec<_> = (0..8)
+                    .map(|page| {
+                        let file = file.clone();
+                        spawn(move || {
+                            let mut buffer = [0; 4096];
+                            file.read_exact_at(&mut buffer, page * 4096).unwrap();
+                            buffer
+                        })
+                    })
+                    .collect();
+
+                for (page, handle) in handles.into_iter().enumerate() {
+                    assert_eq!(handle.join().unwrap(), [page as u8; 4096]);
+                }
+            })
+            .unwrap();
+        }
+
+        #[test]
+        fn fails_to_read_past_end() {
+            start(|| {
+                let path = format!("/tmp/{}", uuid::Uuid::new_v4());
+                write(&path, b"hello").unwrap();
+                let file = File::open(&path).unwrap();
+
+                let mut buffer = [0; 5];
+                let result = file.read_exact_at(&mut buffer, 1);
+
+                assert!(matches!(
+                    result,
+                    Err(crate::Error::Original(error)) if error.kind() == io::ErrorKind::UnexpectedEof
+                ));
+            })
+            .unwrap();
+        }
+
+        #[test]
+        fn writes_and_reads_vectored() {
+            start(|| {
+                let path = format!("/tmp/{}", uuid::Uuid::new_v4());
+                let mut file = File::create(&path).unwrap();
+
+                let bufs = [io::IoSlice::new(b"hello "), io::IoSlice::new(b"world")];
+                assert_eq!(file.write_vectored(&bufs).unwrap(), 11);
+
+                let mut file = File::open(&path).unwrap();
+                let (mut first, mut second) = ([0; 6], [0; 5]);
+                let mut bufs = [
+                    io::IoSliceMut::new(&mut first),
+                    io::IoSliceMut::new(&mut second),
+                ];
+                assert_eq!(file.read_vectored(&mut bufs).unwrap(), 11);
+                assert_eq!(&first, b"hello ");
+                assert_eq!(&second, b"world");
+            })
+            .unwrap();
+        }
+
+        #[test]
+        fn caps_vectored_buffer_count() {
+            start(|| {
+                let path = format!("/tmp/{}", uuid::Uuid::new_v4());
+                let mut file = File::create(&path).unwrap();
+
+                let bufs = vec![io::IoSlice::new(b"a"); 2000];
+                assert_eq!(file.write_vectored(&bufs).unwrap(), 1024);
+
+                let mut file = File::open(&path).unwrap();
+                let mut bytes = vec![[0_u8; 1]; 2000];
+                let mut bufs: Vec<_> = bytes.iter_mut().map(|b| io::IoSliceMut::new(b)).collect();
+                assert_eq!(file.read_vectored(&mut bufs).unwrap(), 1024);
+            })
+            .unwrap();
+        }
+    }
+
+    mod seek {
+        use std::io::{Seek, SeekFrom};
+
+        use super::*;
+
+        #[test]
+        fn uses_kernel_offset_for_fifo() {
+            start(|| {
+                let path = format!("/tmp/{}", uuid::Uuid::new_v4());
+                let path_c = path_to_cstring(Path::new(&path)).unwrap();
+                assert_eq!(unsafe { libc::mkfifo(path_c.as_ptr(), 0o600) }, 0);
+                let mut file = File::options().read(true).write(true).open(&path).unwrap();
+                runtime::pretend_unsupported(io_uring::opcode::Read::CODE);
+                runtime::pretend_unsupported(io_uring::opcode::Write::CODE);
+
+                file.write_all(b"hello").unwrap();
+                let mut buffer = [0; 5];
+                file.read_exact(&mut buffer).unwrap();
+
+                assert_eq!(&buffer, b"hello");
+                let error = file.stream_position().unwrap_err();
+                assert_eq!(error.raw_os_error(), Some(libc::ESPIPE));
+                remove_file(&path).unwrap();
+            })
+            .unwrap();
+        }
+
+        #[test]
+        fn reads_from_end() {
+            start(|| {
+                let path = format!("/tmp/{}", uuid::Uuid::new_v4());
+                write(&path, b"hello world").unwrap();
+                let mut file = File::open(&path).unwrap();
+
+                assert_eq!(file.seek(SeekFrom::End(-5)).unwrap(), 6);
+
+                assert_eq!(io::read_to_string(&mut file).unwrap(), "world");
+                assert_eq!(file.stream_position().unwrap(), 11);
+            })
+            .unwrap();
+        }
+
+        #[test]
+        fn rewinds() {
+            start(|| {
+                let path = format!("/tmp/{}", uuid::Uuid::new_v4());
+                let mut file = File::options()
+                    .read(true)
+                    .write(true)
+                    .create(true)
+                    .open(&path)
+                    .unwrap();
+                file.write_all(b"hello").unwrap();
+
+                file.rewind().unwrap();
+
+                assert_eq!(file.stream_position().unwrap(), 0);
+                assert_eq!(io::read_to_string(&mut file).unwrap(), "hello");
+            })
+            .unwrap();
+        }
+
+        #[test]
+        fn overwrites_relative_to_current() {
+            start(|| {
+                let path = format!("/tmp/{}", uuid::Uuid::new_v4());
+                write(&path, b"hello world").unwrap();
+                let mut file = File::options().write(true).open(&path).unwrap();
+
+                file.seek(SeekFrom::Start(2)).unwrap();
+                file.seek(SeekFrom::Current(4)).unwrap();
+                file.write_all(b"WORLD").unwrap();
+
+                assert_eq!(read(&path).unwrap(), b"hello WORLD");
+            })
+            .unwrap();
+        }
+
+        #[test]
+        fn rejects_negative_position() {
+            start(|| {
+                let mut file = File::open("/etc/hosts").unwrap();
+
+                let error = file.seek(SeekFrom::Current(-1)).unwrap_err();
+
+                assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+                assert_eq!(file.stream_position().unwrap(), 0);
+            })
+            .unwrap();
+        }
+
+        #[test]
+        fn appends_at_end_after_seeking() {
+            start(|| {
+                let path = format!("/tmp/{}", uuid::Uuid::new_v4());
+                write(&path, b"hi ").unwrap();
+                let mut file = File::options().append(true).open(&path).unwrap();
+
+                file.rewind().unwrap();
+                file.write_all(b"hello").unwrap();
+
+                assert_eq!(file.stream_position().unwrap(), 8);
+                assert_eq!(read(&path).unwrap(), b"hi hello");
+            })
+            .unwrap();
+        }
+    }
+
+    mod temporary {
+        use std::os::unix::fs::PermissionsExt;
+
+        use super::*;
+
+        fn entries(directory: &Path) -> Vec<ffi::OsString> {
+            read_dir(directory)
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name())
+                .collect()
+        }
+
+        #[test]
+        fn writes_atomically() {
+            start(|| {
+                let directory = TempDir::new().unwrap();
+                let path = directory.path().join("config");
+
+                write_atomic(&path, b"first").unwrap();
+                assert_eq!(read(&path).unwrap(), b"first");
+
+                File::open(&path)
+                    .unwrap()
+                    .set_permissions(std::fs::Permissions::from_mode(0o600))
+                    .unwrap();
+                write_atomic(&path, b"second").unwrap();
+
+                assert_eq!(read(&path).unwrap(), b"second");
+                assert_eq!(metadata(&path).unwrap().mode() & 0o777, 0o600);
+                assert_eq!(entries(directory.path()), ["config"]);
+            })
+            .unwrap();
+        }
+
+        #[test]
+        fn writes_atomically_without_io_uring() {
+            start(|| {
+                let directory = TempDir::new().unwrap();
+                let path = directory.path().join("config");
+                runtime::pretend_unsupported(io_uring::opcode::LinkAt::CODE);
+                runtime::pretend_unsupported(io_uring::opcode::RenameAt::CODE);
+
+                write_atomic(&path, b"first").unwrap();
+                write_atomic(&path, b"second").unwrap();
+
+                assert_eq!(read(&path).unwrap(), b"second");
+                assert_eq!(entries(directory.path()), ["config"]);
+            })
+            .unwrap();
+        }
+
+        #[test]
+        fn creates_anonymous_file() {
+            start(|| {
+                let directory = TempDir::new().unwrap();
+
+                let file = tempfile_in(&directory).unwrap();
+                file.write_all_at(b"hello", 0).unwrap();
+
+                let mut buffer = [0; 5];
+                file.read_exact_at(&mut buffer, 0).unwrap();
+                assert_eq!(&buffer, b"hello");
+                assert!(entries(directory.path()).is_empty());
+                drop(tempfile().unwrap());
+            })
+            .unwrap();
+        }
+
+        #[test]
+        fn deletes_directory() {
+            start(|| {
+                let directory = TempDir::new().unwrap();
+                let path = directory.path().to_path_buf();
+                create_dir(path.join("nested")).unwrap();
+                write(path.join("nested/file"), b"hello").unwrap();
+                assert_eq!(metadata(&path).unwrap().mode() & 0o777, 0o700);
+
+                drop(directory);
+                assert!(!try_exists(&path).unwrap());
+
+                let directory = TempDir::new().unwrap();
+                let path = directory.path().to_path_buf();
+                directory.close().unwrap();
+                assert!(!try_exists(&path).unwrap());
+            })
+            .unwrap();
+        }
+
+        #[test]
+        fn deletes_directory_when_cancelled() {
+            start(|| {
+                let path = std::rc::Rc::new(std::cell::RefCell::new(None));
+
+                let handle = runtime::spawn({
+                    let path = path.clone();
+                    move || {
+                        let directory = TempDir::new().unwrap();
+                        *path.borrow_mut() = Some(directory.path().to_path_buf());
+                        crate::time::sleep(Duration::from_secs(60))
+                    }
+                });
+                crate::time::sleep(Duration::from_millis(1)).unwrap();
+                handle.cancel();
+                handle.join().unwrap().unwrap_err();
+
+                let path = path.borrow_mut().take().unwrap();
+                assert!(!try_exists(path).unwrap());
+            })
+            .unwrap();
+        }
+
+        #[test]
+        fn deletes_directory_after_runtime_stops() {
+            let directory = start(|| TempDir::new().unwrap()).unwrap();
+            let path = directory.path().to_path_buf();
+
+            drop(directory);
+
+            assert!(!path.exists());
+        }
+    }
+
+    mod xattr {
+        use super::*;
+
+        #[test]
+        fn sets_and_gets() {
+            start(|| {
+                let directory = TempDir::new().unwrap();
+                let path = directory.path().join("file");
+                write(&path, b"hello").unwrap();
+
+                set_xattr(&path, "user.checksum", b"abc").unwrap();
+                assert_eq!(get_xattr(&path, "user.checksum").unwrap().unwrap(), b"abc");
+
+                set_xattr(&path, "user.checksum", b"longer value").unwrap();
+                let value = get_xattr(&path, "user.checksum").unwrap().unwrap();
+                assert_eq!(value, b"longer value");
+
+                set_xattr(&path, "user.empty", b"").unwrap();
+                assert_eq!(get_xattr(&path, "user.empty").unwrap().unwrap(), b"");
+                assert_eq!(get_xattr(&path, "user.missing").unwrap(), None);
+            })
+            .unwrap();
+        }
+
+        #[test]
+        fn lists_and_removes() {
+            start(|| {
+                let directory = TempDir::new().unwrap();
+                let path = directory.path().join("file");
+                write(&path, b"hello").unwrap();
+                set_xattr(&path, "user.a", b"1").unwrap();
+                set_xattr(&path, "user.b", b"2").unwrap();
+
+                let mut names = list_xattr(&path).unwrap();
+                names.retain(|name| name.as_bytes().starts_with(b"user."));
+                names.sort();
+                assert_eq!(names, ["user.a", "user.b"]);
+
+                remove_xattr(&path, "user.a").unwrap();
+                assert_eq!(get_xattr(&path, "user.a").unwrap(), None);
+
+                let error = remove_xattr(&path, "user.a").unwrap_err();
+                let crate::Error::Original(error) = error else {
+                    panic!("expected io error");
+                };
+                assert_eq!(error.raw_os_error(), Some(libc::ENODATA));
+            })
+            .unwrap();
+        }
+
+        #[test]
+        fn uses_file_descriptor() {
+            start(|| {
+                let directory = TempDir::new().unwrap();
+                let path = directory.path().join("file");
+                let file = File::create(&path).unwrap();
+
+                file.set_xattr("user.provenance", b"build 42").unwrap();
+
+                let value = file.get_xattr("user.provenance").unwrap().unwrap();
+                assert_eq!(value, b"build 42");
+                let value = get_xattr(&path, "user.provenance").unwrap().unwrap();
+                assert_eq!(value, b"build 42");
+                assert_eq!(file.get_xattr("user.missing").unwrap(), None);
+            })
+            .unwrap();
+        }
+
+        #[test]
+        fn falls_back_to_helper_thread() {
+            start(|| {
+                let directory = TempDir::new().unwrap();
+                let path = directory.path().join("file");
+                let file = File::create(&path).unwrap();
+                runtime::pretend_unsupported(IORING_OP_SETXATTR);
+                runtime::pretend_unsupported(IORING_OP_GETXATTR);
+                runtime::pretend_unsupported(IORING_OP_FSETXATTR);
+                runtime::pretend_unsupported(IORING_OP_FGETXATTR);
+
+                set_xattr(&path, "user.a", b"1").unwrap();
+                file.set_xattr("user.b", b"2").unwrap();
+
+                assert_eq!(file.get_xattr("user.a").unwrap().unwrap(), b"1");
+                assert_eq!(get_xattr(&path, "user.b").unwrap().unwrap(), b"2");
+                assert_eq!(get_xattr(&path, "user.missing").unwrap(), None);
+            })
+            .unwrap();
+        }
+
+        #[test]
+        fn rejects_nul_byte() {
+            start(|| {
+                let error = get_xattr("/tmp", "user.\0").unwrap_err();
+                let crate::Error::Original(error) = error else {
+                    panic!("expected io error");
+                };
+                assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+            })
+            .unwrap();
+        }
+    }
+
+    mod directories {
+        use super::*;
+
+        #[test]
+        fn creates_and_removes_directory() {
+            start(|| {
+                let path = format!("/tmp/{}", uuid::Uuid::new_v4());
+
+                create_dir(&path).unwrap();
+                assert!(Path::new(&path).is_dir());
+                assert!(matches!(
+                    create_dir(&path),
+                    Err(crate::Error::Original(error)) if error.kind() == io::ErrorKind::AlreadyExists
+                ));
+
+                remove_dir(&path).unwrap();
+                assert!(!Path::new(&path).exists());
+            })
+            .unwrap();
+        }
+
+        #[test]
+        fn creates_and_removes_tree() {
+            start(|| {
+                let root = format!("/tmp/{}", uuid::Uuid::new_v4());
+                let nested = format!("{root}/a/b/c");
+
+                create_dir_all(&nested).unwrap();
+                create_dir_all(&nested).unwrap();
+                write(format!("{nested}/file"), b"hello").unwrap();
+                assert!(matches!(
+                    remove_dir(&root),
+                    Err(crate::Error::Original(error)) if error.raw_os_error() == Some(libc::ENOTEMPTY)
+                ));
+
+                remove_dir_all(&root).unwrap();
+                assert!(!Path::new(&root).exists());
+            })
+            .unwrap();
+        }
+
+        #[test]
+        fn renames_file() {
+            start(|| {
+                let from = format!("/tmp/{}", uuid::Uuid::new_v4());
+                let to = format!("/tmp/{}", uuid::Uuid::new_v4());
+                write(&from, b"hello").unwrap();
+                write(&to, b"replaced").unwrap();
+
+                rename(&from, &to).unwrap();
+
+                assert!(!Path::new(&from).exists());
+                assert_eq!(read(&to).unwrap(), b"hello");
+            })
+            .unwrap();
+        }
+
+        #[test]
+        fn links_file() {
+            start(|| {
+                let original = format!("/tmp/{}", uuid::Uuid::new_v4());
+                let hard = format!("/tmp/{}", uuid::Uuid::new_v4());
+                let soft = format!("/tmp/{}", uuid::Uuid::new_v4());
+                write(&original, b"hello").unwrap();
+
+                hard_link(&original, &hard).unwrap();
+                symlink(&original, &soft).unwrap();
+
+                assert_eq!(read_link(&soft).unwrap(), Path::new(&original));
+                remove_file(&original).unwrap();
+                assert_eq!(read(&hard).unwrap(), b"hello");
+                assert!(File::open(&soft).is_err()); // dangling
+            })
+            .unwrap();
+        }
+
+        #[test]
+        fn rejects_nul_byte() {
+            start(|| {
+                assert!(matches!(
+                    create_dir("/tmp/a\0b"),
+                    Err(crate::Error::Original(error)) if error.kind() == io::ErrorKind::InvalidInput
+                ));
+            })
+            .unwrap();
+        }
+
+        #[test]
+        fn falls_back_to_helper_thread() {
+            start(|| {
+                let root = format!("/tmp/{}", uuid::Uuid::new_v4());
+                for opcode in [
+                    io_uring::opcode::MkDirAt::CODE,
+                    io_uring::opcode::RenameAt::CODE,
+                    io_uring::opcode::SymlinkAt::CODE,
+                    io_uring::opcode::LinkAt::CODE,
+                    io_uring::opcode::UnlinkAt::CODE,
+                ] {
+                    runtime::pretend_unsupported(opcode);
+                }
+
+                create_dir_all(format!("{root}/a")).unwrap();
+                rename(format!("{root}/a"), format!("{root}/b")).unwrap();
+                symlink("b", format!("{root}/c")).unwrap();
+                write(format!("{root}/d"), b"hello").unwrap();
+                hard_link(format!("{root}/d"), format!("{root}/e")).unwrap();
+
+                assert!(Path::new(&format!("{root}/c")).is_dir());
+                assert_eq!(read(format!("{root}/e")).unwrap(), b"hello");
+                remove_dir(format!("{root}/b")).unwrap();
+                remove_dir_all(&root).unwrap();
+            })
+            .unwrap();
+        }
+    }
+
+    mod read_dir {
+        use std::collections::BTreeSet;
+
+        use super::*;
+
+        #[test]
+        fn lists_entries() {
+            start(|| {
+                let root = format!("/tmp/{}", uuid::Uuid::new_v4());
+                create_dir_all(format!("{root}/directory")).unwrap();
+                write(format!("{root}/file"), b"hello").unwrap();
+                symlink("file", format!("{root}/link")).unwrap();
+
+                let mut entries: Vec<_> = read_dir(&root).unwrap().map(Result::unwrap).collect();
+                entries.sort_by_key(DirEntry::file_name);
+
+                let names: Vec<_> = entries.iter().map(DirEntry::file_name).collect();
+                assert_eq!(names, ["directory", "file", "link"]);
+                assert!(entries[0].file_type().unwrap().is_dir());
+                assert!(entries[1].file_type().unwrap().is_file());
+                assert!(entries[2].file_type().unwrap().is_symlink());
+                assert_eq!(entries[1].path(), Path::new(&root).join("file"));
+                assert_eq!(entries[1].metadata().unwrap().len(), 5);
+                assert_eq!(entries[1].ino(), entries[1].metadata().unwrap().ino());
+
+                remove_dir_all(&root).unwrap();
+            })
+            .unwrap();
+        }
+
+        #[test]
+        fn lists_many_entries() {
+            start(|| {
+                let root = format!("/tmp/{}", uuid::Uuid::new_v4());
+                create_dir(&root).unwrap();
+                for i in 0..2000 {
+                    write(format!("{root}/{i}"), b"").unwrap();
+                }
+
+                let names: BTreeSet<_> = read_dir(&root)
+                    .unwrap()
+                    .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+                    .collect();
+
+                assert_eq!(names, (0..2000).map(|i| i.to_string()).collect());
+                remove_dir_all(&root).unwrap();
+            })
+            .unwrap();
+        }
+
+        #[test]
+        fn fails_on_file() {
+            start(|| {
+                assert!(matches!(
+                    read_dir("/etc/hosts"),
+                    Err(crate::Error::Original(error)) if error.raw_os_error() == Some(libc::ENOTDIR)
+                ));
+            })
+            .unwrap();
+        }
+
+        #[test]
+        fn stops_when_cancelled() {
+            start(|| {
+                let mut entries = read_dir("/etc").unwrap();
+
+                runtime::cancel();
+
+                assert!(matches!(
+                    entries.next(),
+                    Some(Err(crate::Error::Cancelled(_)))
+                ));
+                assert!(entries.next().is_none());
+            })
+            .unwrap();
+        }
+    }
+
+    #[test]
+    fn falls_back_to_helper_thread() {
+        start(|| {
+            let path = format!("/tmp/{}", uuid::Uuid::new_v4());
+            for opcode in [
+                io_uring::opcode::OpenAt::CODE,
+                io_uring::opcode::Read::CODE,
+                io_uring::opcode::Write::CODE,
+                io_uring::opcode::Readv::CODE,
+                io_uring::opcode::Close::CODE,
+                io_uring::opcode::UnlinkAt::CODE,
+            ] {
+                runtime::pretend_unsupported(opcode);
+            }
+
+            write(&path, b"hello").unwrap();
+            assert_eq!(read(&path).unwrap(), b"hello");
+            let mut buffer = [0; 4];
+            let mut bufs = [io::IoSliceMut::new(&mut buffer)];
+            let bytes_read = File::open(&path).unwrap().read_vectored(&mut bufs).unwrap();
+            assert_eq!(bytes_read, 4);
+            assert_eq!(&buffer, b"hell");
+
+            remove_file(&path).unwrap();
+            assert!(matches!(
+                File::open(&path),
+                Err(crate::Error::Original(error)) if error.kind() == io::ErrorKind::NotFound
+            ));
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn closes_file_when_cancelled() {
+        start(|| {
+            // fd numbers are reused by tests on other threads, so the close is observed through the pipe
+            let mut fds = [0; 2];
+            let flags = libc::O_CLOEXEC | libc::O_NONBLOCK;
+            assert_eq!(unsafe { libc::pipe2(fds.as_mut_ptr(), flags) }, 0);
+            let mut reader = unsafe { std::fs::File::from_raw_fd(fds[0]) };
+            let file = unsafe { File::from_raw_fd(fds[1]) };
+
+            runtime::cancel();
+            drop(file);
+
+            let mut buffer = [0; 1];
+            assert_eq!(reader.read(&mut buffer).unwrap(), 0); // end of file once every writer is closed
+        })
+        .unwrap();
+    }
+
+    mod watch {
+        use super::*;
+
+        fn next(watcher: &mut Watcher) -> Event {
+            watcher.next().unwrap().unwrap()
+        }
+
+        fn new_dir() -> PathBuf {
+            let root = PathBuf::from(format!("/tmp/{}", uuid::Uuid::new_v4()));
+            create_dir(&root).unwrap();
+            root
+        }
+
+        #[test]
+        fn reports_changes() {
+            start(|| {
+                let root = new_dir();
+                let mut watcher = watch(&root, WatchMask::ALL).unwrap();
+                let path = root.join("file");
+
+                write(&path, b"hello").unwrap();
+                assert_eq!(next(&mut watcher), Event::Created(path.clone()));
+                assert_eq!(next(&mut watcher), Event::Modified(path.clone()));
+                assert_eq!(next(&mut watcher), Event::Written(path.clone()));
+
+                remove_file(&path).unwrap();
+                assert_eq!(next(&mut watcher), Event::Deleted(path));
+
+                remove_dir(&root).unwrap();
+                assert_eq!(next(&mut watcher), Event::Deleted(root));
+                assert!(watcher.next().is_none());
+            })
+            .unwrap();
+        }
+
+        #[test]
+        fn filters_by_mask() {
+            start(|| {
+                let root = new_dir();
+                let mut watcher = watch(&root, WatchMask::CREATE | WatchMask::DELETE).unwrap();
+                let path = root.join("file");
+
+                write(&path, b"hello").unwrap();
+                remove_file(&path).unwrap();
+
+                assert_eq!(next(&mut watcher), Event::Created(path.clone()));
+                assert_eq!(next(&mut watcher), Event::Deleted(path));
+                remove_dir_all(&root).unwrap();
+            })
+            .unwrap();
+        }
+
+        #[test]
+        fn watches_file() {
+            start(|| {
+                let root = new_dir();
+                let path = root.join("config");
+                write(&path, b"a").unwrap();
+                let mut watcher = watch(&path, WatchMask::CLOSE_WRITE).unwrap();
+
+                write(&path, b"b").unwrap();
+
+                assert_eq!(next(&mut watcher), Event::Written(path));
+                remove_dir_all(&root).unwrap();
+            })
+            .unwrap();
+        }
+
+        #[test]
+        fn pairs_moves() {
+            start(|| {
+                let root = new_dir();
+                let (from, to) = (root.join("from"), root.join("to"));
+                write(&from, b"hello").unwrap();
+                let mut watcher = watch(&root, WatchMask::MOVE).unwrap();
+
+                rename(&from, &to).unwrap();
+
+                let event = Event::Moved {
+                    from: Some(from),
+                    to: Some(to),
+                };
+                assert_eq!(next(&mut watcher), event);
+                remove_dir_all(&root).unwrap();
+            })
+            .unwrap();
+        }
+
+        #[test]
+        fn watches_recursively() {
+            start(|| {
+                let root = new_dir();
+                create_dir(root.join("existing")).unwrap();
+                let mask = WatchMask::CREATE | WatchMask::RECURSIVE;
+                let mut watcher = watch(&root, mask).unwrap();
+
+                write(root.join("existing/file"), b"").unwrap();
+                assert_eq!(
+                    next(&mut watcher),
+                    Event::Created(root.join("existing/file"))
+                );
+
+                // created before its directory was watched
+                create_dir_all(root.join("new/nested")).unwrap();
+                assert_eq!(next(&mut watcher), Event::Created(root.join("new")));
+                assert_eq!(next(&mut watcher), Event::Created(root.join("new/nested")));
+
+                write(root.join("new/nested/file"), b"").unwrap();
+                let path = root.join("new/nested/file");
+                assert_eq!(next(&mut watcher), Event::Created(path));
+
+                remove_dir_all(&root).unwrap();
+            })
+            .unwrap();
+        }
+
+        #[test]
+        fn follows_renamed_directories() {
+            start(|| {
+                let root = new_dir();
+                create_dir_all(root.join("before/nested")).unwrap();
+                let mask = WatchMask::CREATE | WatchMask::RECURSIVE;
+                let mut watcher = watch(&root, mask).unwrap();
+
+                rename(root.join("before"), root.join("after")).unwrap();
+                write(root.join("after/nested/file"), b"").unwrap();
+
+                let path = root.join("after/nested/file");
+                assert_eq!(next(&mut watcher), Event::Created(path));
+                remove_dir_all(&root).unwrap();
+            })
+            .unwrap();
+        }
+
+        #[test]
+        fn reports_overflow() {
+            start(|| {
+                let root = new_dir();
+                let mut watcher = watch(&root, WatchMask::CREATE).unwrap();
+                let limit = std::fs::read_to_string("/proc/sys/fs/inotify/max_queued_events");
+                let limit: usize = limit.unwrap().trim().parse().unwrap();
+
+                for i in 0..=limit {
+                    std::fs::File::create(root.join(i.to_string())).unwrap();
+                }
+
+                let mut events = watcher.by_ref().map(Result::unwrap);
+                assert!(events.any(|event| event == Event::Overflowed));
+                remove_dir_all(&root).unwrap();
+            })
+            .unwrap();
+        }
+
+        #[test]
+        fn stops_when_cancelled() {
+            start(|| {
+                let root = new_dir();
+                let mut watcher = watch(&root, WatchMask::ALL).unwrap();
+
+                let handle = runtime::spawn(move || watcher.next());
+                crate::time::sleep(Duration::from_millis(1)).unwrap();
+                handle.cancel();
+
+                let result = handle.join().unwrap().unwrap();
+                assert!(matches!(result, Err(crate::Error::Cancelled(_))));
+                remove_dir(&root).unwrap();
+            })
+            .unwrap();
+        }
+    }
+
+    mod space {
+        use super::*;
+
+        fn new_file() -> (String, File) {
+            let path = format!("/tmp/{}", uuid::Uuid::new_v4());
+            let file = File::options()
+                .read(true)
+                .write(true)
+                .create(true)
+                .open(&path)
+                .unwrap();
+            (path, file)
+        }
+
+        #[test]
+        fn sets_length() {
+            start(|| {
+                let (path, file) = new_file();
+                file.write_all_at(b"hello world", 0).unwrap();
+
+                file.set_len(5).unwrap();
+                assert_eq!(read(&path).unwrap(), b"hello");
+
+                file.set_len(8).unwrap();
+                assert_eq!(read(&path).unwrap(), b"hello\0\0\0");
+            })
+            .unwrap();
+        }
+
+        #[test]
+        fn preallocates_without_changing_size() {
+            start(|| {
+                let (_path, file) = new_file();
+
+                file.allocate(0, 1024 * 1024, AllocateMode::KeepSize)
+                    .unwrap();
+
+                let metadata = file.metadata().unwrap();
+                assert_eq!(metadata.len(), 0);
+                assert!(metadata.blocks() * 512 >= 1024 * 1024);
+            })
+            .unwrap();
+        }
+
+        #[test]
+        fn extends_file() {
+            start(|| {
+                let (_path, file) = new_file();
+
+                file.allocate(4096, 4096, AllocateMode::Extend).unwrap();
+
+                assert_eq!(file.metadata().unwrap().len(), 8192);
+            })
+            .unwrap();
+        }
+
+        #[test]
+        fn punches_hole() {
+            start(|| {
+                let (path, file) = new_file();
+                file.write_all_at(&[1; 3 * 4096], 0).unwrap();
+
+                file.allocate(4096, 4096, AllocateMode::PunchHole).unwrap();
+
+                let contents = read(&path).unwrap();
+                assert_eq!(contents.len(), 3 * 4096);
+                assert!(contents[..4096].iter().all(|&byte| byte == 1));
+                assert!(contents[4096..8192].iter().all(|&byte| byte == 0));
+                assert!(contents[8192..].iter().all(|&byte| byte == 1));
+            })
+            .unwrap();
+        }
+
+        #[test]
+        fn zeroes_range() {
+            start(|| {
+                let (path, file) = new_file();
+                file.write_all_at(&[1; 2 * 4096], 0).unwrap();
+
+                match file.allocate(0, 4096, AllocateMode::ZeroRange) {
+                    Ok(()) => {}
+                    // e.g. tmpfs
+                    Err(crate::Error::Original(error))
+                        if error.raw_os_error() == Some(libc::EOPNOTSUPP) =>
+                    {
+                        return;
+                    }
+                    Err(error) => panic!("{error:?}"),
+                }
+
+                let contents = read(&path).unwrap();
+                assert!(contents[..4096].iter().all(|&byte| byte == 0));
+                assert!(contents[4096..].iter().all(|&byte| byte == 1));
+            })
+            .unwrap();
+        }
+
+        #[test]
+        fn advises_access_pattern() {
+            start(|| {
+                let file = File::open("/etc/hosts").unwrap();
+
+                file.advise(0, 0, Advice::Sequential).unwrap();
+                file.advise(0, u64::MAX / 2, Advice::WillNeed).unwrap();
+                file.advise(0, 4096, Advice::DontNeed).unwrap();
+            })
+            .unwrap();
+        }
+
+        #[test]
+        fn falls_back_to_helper_thread() {
+            start(|| {
+                let (_path, file) = new_file();
+                runtime::pretend_unsupported(io_uring::opcode::Fallocate::CODE);
+                runtime::pretend_unsupported(io_uring::opcode::Fadvise::CODE);
+                runtime::pretend_unsupported(IORING_OP_FTRUNCATE);
+
+                file.allocate(0, 4096, AllocateMode::Extend).unwrap();
+                file.advise(0, 0, Advice::Random).unwrap();
+                assert_eq!(file.metadata().unwrap().len(), 4096);
+
+                file.set_len(100).unwrap();
+                assert_eq!(file.metadata().unwrap().len(), 100);
+            })
+            .unwrap();
+        }
+    }
+
+    mod direct {
+        use super::*;
+
+        fn new_direct_file() -> File {
+            let path = format!("/tmp/{}", uuid::Uuid::new_v4());
+            File::options()
+                .read(true)
+                .write(true)
+                .create(true)
+                .custom_flags(libc::O_DIRECT)
+                .open(path)
+                .unwrap()
+        }
+
+        #[test]
+        fn roundtrips() {
+            start(|| {
+                let file = new_direct_file();
+                let mut buf = AlignedBuf::new(&file, 1).unwrap();
+                assert_eq!(buf.len(), buf.alignment());
+                buf.fill(b'x');
+
+                let bytes_wrote = file.write_direct_at(&buf, 0).unwrap();
+                assert_eq!(bytes_wrote, buf.len());
+                let bytes_wrote = file.write_direct_at(&buf, buf.len() as u64).unwrap();
+                assert_eq!(bytes_wrote, buf.len());
+
+                buf.fill(0);
+                let bytes_read = file.read_direct_at(&mut buf, 0).unwrap();
+                assert_eq!(bytes_read, buf.len());
+                assert!(buf.iter().all(|&byte| byte == b'x'));
+
+                let end = 2 * buf.len() as u64;
+                assert_eq!(file.read_direct_at(&mut buf, end).unwrap(), 0);
+            })
+            .unwrap();
+        }
+
+        #[test]
+        fn rejects_unaligned_offset() {
+            start(|| {
+                let file = new_direct_file();
+                let mut buf = AlignedBuf::with_alignment(4096, 4096);
+
+                let error = file.read_direct_at(&mut buf, 512).unwrap_err();
+                let crate::Error::Original(error) = error else {
+                    panic!("expected io error");
+                };
+                assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+            })
+            .unwrap();
+        }
+
+        #[test]
+        fn caps_length_at_alignment() {
+            assert_eq!(direct_length(8192, 4096), 8192);
+            assert_eq!(direct_length(4 << 30, 4096), READ_LIMIT / 4096 * 4096);
+            assert_eq!(direct_length(usize::MAX, 512) % 512, 0);
+        }
+
+        #[test]
+        fn buffer_outlives_runtime() {
+            let buf = start(|| {
+                let file = new_direct_file();
+                let buf = AlignedBuf::with_alignment(4096, 4096);
+                file.write_direct_at(&buf, 0).unwrap();
+                buf
+            })
+            .unwrap();
+
+            start(move || {
+                let file = new_direct_file();
+                file.write_direct_at(&buf, 0).unwrap();
+                drop(buf);
+            })
+            .unwrap();
+        }
+
+        #[test]
+        fn falls_back_to_helper_thread() {
+            start(|| {
+                let file = new_direct_file();
+                let mut buf = AlignedBuf::with_alignment(4096, 4096);
+                buf.fill(b'y');
+                runtime::pretend_unsupported(io_uring::opcode::WriteFixed::CODE);
+                runtime::pretend_unsupported(io_uring::opcode::ReadFixed::CODE);
+
+                file.write_direct_at(&buf, 0).unwrap();
+                buf.fill(0);
+                file.read_direct_at(&mut buf, 0).unwrap();
+
+                assert!(buf.iter().all(|&byte| byte == b'y'));
+            })
+            .unwrap();
+        }
+    }
+
+    mod copy {
+        use super::*;
+
+        #[test]
+        fn copies_file() {
+            start(|| {
+                let path = format!("/tmp/{}", uuid::Uuid::new_v4());
+
+                copy("/etc/hosts", &path).unwrap();
+
+                assert_eq!(read("/etc/hosts").unwrap(), read(&path).unwrap());
+            })
+            .unwrap();
+        }
+
+        #[test]
+        fn overwrites_with_permissions() {
+            start(|| {
+                let from = format!("/tmp/{}", uuid::Uuid::new_v4());
+                let to = format!("/tmp/{}", uuid::Uuid::new_v4());
+                write(&from, b"hello").unwrap();
+                File::open(&from)
+                    .unwrap()
+                    .set_permissions(std::fs::Permissions::from_mode(0o640))
+                    .unwrap();
+                write(&to, b"previous contents").unwrap();
+
+                assert_eq!(copy(&from, &to).unwrap(), 5);
+
+                assert_eq!(read(&to).unwrap(), b"hello");
+                assert_eq!(metadata(&to).unwrap().mode() & 0o777, 0o640);
+            })
+            .unwrap();
+        }
+
+        #[test]
+        fn reports_progress() {
+            start(|| {
+                let from = format!("/tmp/{}", uuid::Uuid::new_v4());
+                let to = format!("/tmp/{}", uuid::Uuid::new_v4());
+                let contents: Vec<u8> = (0..20 * 1024 * 1024).map(|i| i as u8).collect();
+                write(&from, &contents).unwrap();
+
+                let mut reports = vec![];
+                let copied = copy_with_progress(&from, &to, |copied| reports.push(copied)).unwrap();
+
+                assert_eq!(copied, contents.len() as u64);
+                assert!(reports.windows(2).all(|pair| pair[0] < pair[1]));
+                assert_eq!(reports.last(), Some(&copied));
+                assert!(read(&to).unwrap() == contents);
+            })
+            .unwrap();
+        }
+
+        #[test]
+        fn rejects_directory() {
+            start(|| {
+                let to = format!("/tmp/{}", uuid::Uuid::new_v4());
+
+                assert!(matches!(
+                    copy("/tmp", &to),
+                    Err(crate::Error::Original(error)) if error.kind() == io::ErrorKind::InvalidInput
+                ));
+            })
+            .unwrap();
+        }
+
+        #[test]
+        fn stops_when_cancelled() {
+            start(|| {
+                let to = format!("/tmp/{}", uuid::Uuid::new_v4());
+
+                runtime::cancel();
+
+                assert!(matches!(
+                    copy("/etc/hosts", &to),
+                    Err(crate::Error::Cancelled(_))
+                ));
+            })
+            .unwrap();
+        }
+    }
+
+    #[test]
+    fn truncates_file() {
+        start(|| {
+            let path = format!("/tmp/{}", uuid::Uuid::new_v4());
+            write(&path, b"hi").unwrap();
+
+            write(&path, b"hello").unwrap();
+
+            assert_eq!(read(&path).unwrap(), b"hello");
+        })
+        .unwrap();
     }
 
     #[test]
@@ -425,25 +3533,25 @@ mod tests {
         .unwrap();
     }
 
-    #[test]
-    fn queries_metadata() {
-        start(|| {
-            let uringy = metadata("/etc/hosts").unwrap();
-            let std = std::fs::metadata("/etc/hosts").unwrap();
-
-            // core
-            assert_eq!(uringy.file_type(), std.file_type());
-            assert_eq!(uringy.is_dir(), std.is_dir());
-            assert_eq!(uringy.is_file(), std.is_file());
-            assert_eq!(uringy.is_symlink(), std.is_symlink());
-            assert_eq!(uringy.len(), std.len());
-            assert_eq!(uringy.permissions(), std.permissions());
-            // assert_eq!(uringy.modified(), std.modified());
-            // assert_eq!(uringy.accessed(), std.accessed());
-            // assert_eq!(uringy.created(), std.created());
-
-            {
-                use std::os::unix::fs::MetadataExt;
+    mod metadata {
+        use std::os::unix::fs::MetadataExt;
+
+        use super::*;
+
+        #[test]
+        fn matches_std() {
+            start(|| {
+                let uringy = metadata("/etc/hosts").unwrap();
+                let std = std::fs::metadata("/etc/hosts").unwrap();
+
+                assert_eq!(uringy.is_dir(), std.is_dir());
+                assert_eq!(uringy.is_file(), std.is_file());
+                assert_eq!(uringy.is_symlink(), std.is_symlink());
+                assert_eq!(uringy.len(), std.len());
+                assert_eq!(uringy.permissions(), std.permissions());
+                assert_eq!(uringy.modified().unwrap(), std.modified().unwrap());
+                assert_eq!(uringy.accessed().unwrap(), std.accessed().unwrap());
+                assert_eq!(uringy.created().ok(), std.created().ok());
 
                 assert_eq!(uringy.dev(), std.dev());
                 assert_eq!(uringy.ino(), std.ino());
@@ -461,30 +3569,82 @@ mod tests {
                 assert_eq!(uringy.ctime_nsec(), std.ctime_nsec());
                 assert_eq!(uringy.blksize(), std.blksize());
                 assert_eq!(uringy.blocks(), std.blocks());
-            }
+            })
+            .unwrap();
+        }
 
-            {
-                use std::os::linux::fs::MetadataExt;
-
-                // assert_eq!(uringy.as_raw_stat(), std.as_raw_stat());
-                assert_eq!(uringy.st_dev(), std.st_dev());
-                assert_eq!(uringy.st_ino(), std.st_ino());
-                assert_eq!(uringy.st_mode(), std.st_mode());
-                assert_eq!(uringy.st_nlink(), std.st_nlink());
-                assert_eq!(uringy.st_uid(), std.st_uid());
-                assert_eq!(uringy.st_gid(), std.st_gid());
-                assert_eq!(uringy.st_rdev(), std.st_rdev());
-                assert_eq!(uringy.st_size(), std.st_size());
-                assert_eq!(uringy.st_atime(), std.st_atime());
-                assert_eq!(uringy.st_atime_nsec(), std.st_atime_nsec());
-                assert_eq!(uringy.st_mtime(), std.st_mtime());
-                assert_eq!(uringy.st_mtime_nsec(), std.st_mtime_nsec());
-                assert_eq!(uringy.st_ctime(), std.st_ctime());
-                assert_eq!(uringy.st_ctime_nsec(), std.st_ctime_nsec());
-                assert_eq!(uringy.st_blksize(), std.st_blksize());
-                assert_eq!(uringy.st_blocks(), std.st_blocks());
-            }
-        })
-        .unwrap();
+        #[test]
+        fn queries_open_file() {
+            start(|| {
+                let file = File::open("/etc/hosts").unwrap();
+
+                let metadata = file.metadata().unwrap();
+
+                assert_eq!(
+                    metadata.ino(),
+                    std::fs::metadata("/etc/hosts").unwrap().ino()
+                );
+                assert!(metadata.mount_id().is_some());
+            })
+            .unwrap();
+        }
+
+        #[test]
+        fn doesnt_need_read_permission() {
+            start(|| {
+                let path = format!("/tmp/{}", uuid::Uuid::new_v4());
+                write(&path, b"hello").unwrap();
+                File::open(&path)
+                    .unwrap()
+                    .set_permissions(std::fs::Permissions::from_mode(0o000))
+                    .unwrap();
+
+                assert_eq!(metadata(&path).unwrap().len(), 5);
+                remove_file(&path).unwrap();
+            })
+            .unwrap();
+        }
+
+        #[test]
+        fn follows_symlinks_unless_asked() {
+            start(|| {
+                let path = format!("/tmp/{}", uuid::Uuid::new_v4());
+                symlink("/etc/hosts", &path).unwrap();
+
+                assert!(metadata(&path).unwrap().is_file());
+                assert!(symlink_metadata(&path).unwrap().is_symlink());
+                remove_file(&path).unwrap();
+            })
+            .unwrap();
+        }
+
+        #[test]
+        fn checks_existence() {
+            start(|| {
+                let path = format!("/tmp/{}", uuid::Uuid::new_v4());
+
+                assert!(try_exists("/etc/hosts").unwrap());
+                assert!(!try_exists(&path).unwrap());
+                symlink("/nonexistent", &path).unwrap();
+                assert!(!try_exists(&path).unwrap()); // dangling
+                remove_file(&path).unwrap();
+            })
+            .unwrap();
+        }
+
+        #[test]
+        fn falls_back_to_helper_thread() {
+            start(|| {
+                runtime::pretend_unsupported(io_uring::opcode::Statx::CODE);
+
+                let metadata = metadata("/etc/hosts").unwrap();
+
+                assert_eq!(
+                    metadata.len(),
+                    std::fs::metadata("/etc/hosts").unwrap().len()
+                );
+            })
+            .unwrap();
+        }
     }
 }
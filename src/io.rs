@@ -0,0 +1,838 @@
+//! Standard streams and pipes inspired by the standard library.
+//!
+//! Reads and writes go through io_uring, so they only park the calling fiber.
+
+use std::cell::{Cell, RefCell};
+use std::io::{Read, Write};
+use std::os::fd::{AsRawFd, RawFd};
+use std::{cmp, fmt, io, mem};
+
+use crate::runtime;
+
+/// Number of bytes buffered by [Stdout] and [Stderr] before they're flushed, even without a newline.
+const WRITE_CAPACITY: usize = 8 * 1024;
+
+/// Number of bytes [Stdin] reads at once.
+const READ_CAPACITY: usize = 8 * 1024;
+
+/// Number of bytes [copy] moves at once, which is the default capacity of a pipe.
+const COPY_CHUNK: usize = 64 * 1024;
+
+/// Offset that makes io_uring use and advance the file position, like `read(2)` and `write(2)`.
+const CURRENT_POSITION: u64 = u64::MAX;
+
+/// Prints to the standard output.
+///
+/// Like [std::print], but only parks the fiber while flushing.
+#[macro_export]
+macro_rules! print {
+    ($($arg:tt)*) => {
+        $crate::io::_print(format_args!($($arg)*))
+    };
+}
+
+/// Prints to the standard output, with a newline.
+///
+/// Like [std::println], but only parks the fiber while flushing.
+#[macro_export]
+macro_rules! println {
+    () => {
+        $crate::print!("\n")
+    };
+    ($($arg:tt)*) => {
+        $crate::io::_print(format_args!("{}\n", format_args!($($arg)*)))
+    };
+}
+
+/// Prints to the standard error.
+///
+/// Like [std::eprint], but only parks the fiber while flushing.
+#[macro_export]
+macro_rules! eprint {
+    ($($arg:tt)*) => {
+        $crate::io::_eprint(format_args!($($arg)*))
+    };
+}
+
+/// Prints to the standard error, with a newline.
+///
+/// Like [std::eprintln], but only parks the fiber while flushing.
+#[macro_export]
+macro_rules! eprintln {
+    () => {
+        $crate::eprint!("\n")
+    };
+    ($($arg:tt)*) => {
+        $crate::io::_eprint(format_args!("{}\n", format_args!($($arg)*)))
+    };
+}
+
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments) {
+    if !runtime::is_running() {
+        let result = write_blocking(libc::STDOUT_FILENO, args);
+        return result.expect("failed printing to stdout");
+    }
+
+    stdout().write_fmt(args).expect("failed printing to stdout");
+}
+
+#[doc(hidden)]
+pub fn _eprint(args: fmt::Arguments) {
+    if !runtime::is_running() {
+        let result = write_blocking(libc::STDERR_FILENO, args);
+        return result.expect("failed printing to stderr");
+    }
+
+    stderr().write_fmt(args).expect("failed printing to stderr");
+}
+
+/// Prints from threads without a runtime, like the helper thread, or after [runtime::start] returns.
+fn write_blocking(fd: RawFd, args: fmt::Arguments) -> io::Result<()> {
+    let message = fmt::format(args); // written at once, so lines from different threads don't interleave
+    let mut buffer = message.as_bytes();
+    while !buffer.is_empty() {
+        match unsafe { libc::write(fd, buffer.as_ptr().cast(), buffer.len()) } {
+            -1 => {
+                let error = io::Error::last_os_error();
+                if error.kind() != io::ErrorKind::Interrupted {
+                    return Err(error);
+                }
+            }
+            bytes_wrote => buffer = &buffer[bytes_wrote as usize..],
+        }
+    }
+
+    Ok(())
+}
+
+/// Creates an anonymous pipe, whatever is written to the writer can be read from the reader.
+pub fn pipe() -> crate::IoResult<(PipeReader, PipeWriter)> {
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } == -1 {
+        let error = io::Error::last_os_error();
+        return Err(crate::Error::Original(error));
+    }
+
+    Ok((PipeReader(Fd(fds[0])), PipeWriter(Fd(fds[1]))))
+}
+
+/// Reading end of a [pipe], sees end of file once every writer is dropped.
+#[derive(Debug)]
+pub struct PipeReader(Fd);
+
+impl Read for PipeReader {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        read(self.0 .0, buffer)
+    }
+}
+
+impl Read for &PipeReader {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        read(self.0 .0, buffer)
+    }
+}
+
+impl AsRawFd for PipeReader {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0 .0
+    }
+}
+
+/// Writing end of a [pipe].
+#[derive(Debug)]
+pub struct PipeWriter(Fd);
+
+impl Write for PipeWriter {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        write(self.0 .0, buffer)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl AsRawFd for PipeWriter {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0 .0
+    }
+}
+
+/// Copies everything from the reader to the writer until end of file, returning the number of bytes copied.
+///
+/// Data is spliced through an internal pipe, so it never passes through userspace.
+/// Falls back to copying through a buffer if either end doesn't support splicing.
+///
+/// When cancelled, data that was read but not yet written is lost.
+pub fn copy(reader: &impl AsRawFd, writer: &impl AsRawFd) -> crate::IoResult<u64> {
+    copy_fds(reader.as_raw_fd(), writer.as_raw_fd(), &mut |_| {})
+}
+
+/// Copies in both directions at once until both reach end of file, returning the number of bytes copied from a to b and from b to a.
+///
+/// Once a direction reaches end of file, the writing side of the other end is shut down if it's a socket.
+/// Intended for proxies, see [copy].
+pub fn copy_bidirectional(a: &impl AsRawFd, b: &impl AsRawFd) -> crate::IoResult<(u64, u64)> {
+    let (a, b) = (a.as_raw_fd(), b.as_raw_fd());
+
+    let handle = runtime::spawn(move || {
+        let result = copy_fds(b, a, &mut |_| {});
+        unsafe { libc::shutdown(a, libc::SHUT_WR) }; // fails harmlessly for non-sockets
+        result
+    });
+    let mut guard = CopyGuard(Some(handle));
+
+    let result = copy_fds(a, b, &mut |_| {});
+    match &result {
+        Ok(_) => {
+            unsafe { libc::shutdown(b, libc::SHUT_WR) };
+        }
+        Err(crate::Error::Cancelled(reason)) => guard.cancel_with(*reason),
+        Err(crate::Error::Original(_)) => guard.cancel_with(crate::CancellationReason::Requested),
+    };
+
+    let other = match guard.0.take().unwrap().join() {
+        Ok(result) => result,
+        Err(crate::Error::Cancelled(reason)) => Err(crate::Error::Cancelled(reason)),
+        Err(crate::Error::Original(payload)) => std::panic::resume_unwind(payload),
+    };
+
+    Ok((result?, other?))
+}
+
+/// Cancels and joins the other direction of [copy_bidirectional] if it's dropped first, since it can't outlive the borrowed fds.
+struct CopyGuard(Option<runtime::JoinHandle<crate::IoResult<u64>>>);
+
+impl CopyGuard {
+    fn cancel_with(&self, reason: crate::CancellationReason) {
+        self.0.as_ref().unwrap().cancel_with(reason);
+    }
+}
+
+impl Drop for CopyGuard {
+    fn drop(&mut self) {
+        if let Some(handle) = self.0.take() {
+            let reason = runtime::cancellation_reason();
+            handle.cancel_with(reason.unwrap_or(crate::CancellationReason::Requested));
+            let _ = runtime::shield(|| handle.join());
+        }
+    }
+}
+
+/// Like [copy], calling `progress` with the number of bytes copied so far after every chunk.
+pub(crate) fn copy_fds(
+    reader: RawFd,
+    writer: RawFd,
+    progress: &mut dyn FnMut(u64),
+) -> crate::IoResult<u64> {
+    if !runtime::supports(io_uring::opcode::Splice::CODE) {
+        return copy_buffered(reader, writer, 0, progress);
+    }
+
+    let (pipe_reader, pipe_writer) = pipe()?;
+    let mut copied = 0;
+
+    loop {
+        let result = splice(reader, pipe_writer.as_raw_fd(), COPY_CHUNK);
+        let spliced = match result {
+            Ok(0) => return Ok(copied),
+            Ok(spliced) => spliced,
+            // can only fall back before anything is stuck in the pipe
+            Err(crate::Error::Original(error)) if error.raw_os_error() == Some(libc::EINVAL) => {
+                return copy_buffered(reader, writer, copied, progress);
+            }
+            Err(error) => return Err(error),
+        };
+
+        let mut remaining = spliced;
+        while remaining > 0 {
+            match splice(pipe_reader.as_raw_fd(), writer, remaining) {
+                Ok(0) => {
+                    let error = io::Error::from(io::ErrorKind::WriteZero);
+                    return Err(crate::Error::Original(error));
+                }
+                Ok(written) => remaining -= written,
+                // e.g. writer was opened with O_APPEND, so take back what's stuck in the pipe
+                Err(crate::Error::Original(error))
+                    if error.raw_os_error() == Some(libc::EINVAL) =>
+                {
+                    let mut buffer = vec![0; remaining];
+                    let mut pipe_reader = &pipe_reader;
+                    pipe_reader
+                        .read_exact(&mut buffer)
+                        .map_err(crate::Error::from_io_error)?;
+                    write_all(writer, &buffer)?;
+
+                    copied += spliced as u64;
+                    progress(copied);
+                    return copy_buffered(reader, writer, copied, progress);
+                }
+                Err(error) => return Err(error),
+            }
+        }
+
+        copied += spliced as u64;
+        progress(copied);
+    }
+}
+
+fn splice(from: RawFd, to: RawFd, length: usize) -> crate::IoResult<usize> {
+    let (from, to) = (io_uring::types::Fd(from), io_uring::types::Fd(to));
+    let sqe = io_uring::opcode::Splice::new(from, -1, to, -1, length as u32)
+        .flags(libc::SPLICE_F_MOVE)
+        .build();
+    let spliced = runtime::syscall(sqe)?;
+    Ok(spliced as usize)
+}
+
+fn copy_buffered(
+    reader: RawFd,
+    writer: RawFd,
+    mut copied: u64,
+    progress: &mut dyn FnMut(u64),
+) -> crate::IoResult<u64> {
+    let mut buffer = vec![0; COPY_CHUNK];
+
+    loop {
+        let bytes_read = match read(reader, &mut buffer) {
+            Ok(0) => return Ok(copied),
+            Ok(bytes_read) => bytes_read,
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+            Err(error) => return Err(crate::Error::from_io_error(error)),
+        };
+
+        write_all(writer, &buffer[..bytes_read])?;
+        copied += bytes_read as u64;
+        progress(copied);
+    }
+}
+
+fn write_all(fd: RawFd, buffer: &[u8]) -> crate::IoResult<()> {
+    let mut written = 0;
+    while written < buffer.len() {
+        match write(fd, &buffer[written..]) {
+            Ok(0) => {
+                let error = io::Error::from(io::ErrorKind::WriteZero);
+                return Err(crate::Error::Original(error));
+            }
+            Ok(n) => written += n,
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => {}
+            Err(error) => return Err(crate::Error::from_io_error(error)),
+        }
+    }
+
+    Ok(())
+}
+
+/// Buffered output, shared by every handle on the thread.
+struct Output {
+    fd: RawFd,
+    buffer: RefCell<Vec<u8>>,
+    is_flushing: Cell<bool>, // keeps concurrent flushes in order
+}
+
+impl Output {
+    const fn new(fd: RawFd) -> Self {
+        Output {
+            fd,
+            buffer: RefCell::new(Vec::new()),
+            is_flushing: Cell::new(false),
+        }
+    }
+
+    fn write(&self, data: &[u8], is_line_buffered: bool) -> io::Result<usize> {
+        let should_flush = {
+            let mut buffer = self.buffer.borrow_mut();
+            buffer.extend_from_slice(data);
+            buffer.len() >= WRITE_CAPACITY || (is_line_buffered && data.contains(&b'\n'))
+        };
+
+        if should_flush {
+            self.flush()?;
+        }
+
+        Ok(data.len())
+    }
+
+    /// Writes out the buffer, unless another fiber is already doing so.
+    /// The buffer can't stay borrowed while parked, so it's taken out for every write.
+    fn flush(&self) -> io::Result<()> {
+        if self.is_flushing.replace(true) {
+            return Ok(()); // picks up whatever was buffered in the meantime
+        }
+
+        struct Finish<'a>(&'a Cell<bool>);
+
+        impl Drop for Finish<'_> {
+            fn drop(&mut self) {
+                self.0.set(false);
+            }
+        }
+
+        let _finish = Finish(&self.is_flushing);
+
+        loop {
+            let mut data = mem::take(&mut *self.buffer.borrow_mut());
+            if data.is_empty() {
+                return Ok(());
+            }
+
+            let mut written = 0;
+            while written < data.len() {
+                match write(self.fd, &data[written..]) {
+                    Ok(0) => {
+                        let error = io::Error::from(io::ErrorKind::WriteZero);
+                        self.unflushed(&mut data, written);
+                        return Err(error);
+                    }
+                    Ok(n) => written += n,
+                    Err(error) if error.kind() == io::ErrorKind::Interrupted => {}
+                    Err(error) => {
+                        self.unflushed(&mut data, written);
+                        return Err(error);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Puts data that failed to be written back in front of the buffer.
+    fn unflushed(&self, data: &mut Vec<u8>, written: usize) {
+        let mut buffer = self.buffer.borrow_mut();
+        data.drain(..written);
+        data.append(&mut buffer);
+        *buffer = mem::take(data);
+    }
+}
+
+impl Drop for Output {
+    /// Last resort for output that was never flushed, like a [print!] without a newline.
+    fn drop(&mut self) {
+        let buffer = self.buffer.get_mut();
+        let mut written = 0;
+        while written < buffer.len() {
+            let remaining = &buffer[written..];
+            let result =
+                unsafe { libc::write(self.fd, remaining.as_ptr().cast(), remaining.len()) };
+            if result <= 0 {
+                break;
+            }
+            written += result as usize;
+        }
+    }
+}
+
+thread_local! {
+    static STDOUT: Output = const { Output::new(libc::STDOUT_FILENO) };
+    static STDERR: Output = const { Output::new(libc::STDERR_FILENO) };
+    static STDIN: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Handle to the standard output, see [stdout].
+#[derive(Debug, Copy, Clone)]
+pub struct Stdout(());
+
+/// Returns a handle to the standard output.
+///
+/// It's line buffered, and the buffer is shared by every handle on the thread.
+pub fn stdout() -> Stdout {
+    Stdout(())
+}
+
+impl Write for Stdout {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        STDOUT.with(|output| output.write(buffer, true))
+    }
+
+    /// Returns early if another fiber is already flushing, it writes out everything that's buffered before finishing.
+    fn flush(&mut self) -> io::Result<()> {
+        STDOUT.with(|output| output.flush())
+    }
+}
+
+impl AsRawFd for Stdout {
+    fn as_raw_fd(&self) -> RawFd {
+        libc::STDOUT_FILENO
+    }
+}
+
+/// Handle to the standard error, see [stderr].
+#[derive(Debug, Copy, Clone)]
+pub struct Stderr(());
+
+/// Returns a handle to the standard error.
+///
+/// Every write is flushed, but it's still buffered so that concurrent fibers don't interleave their output.
+pub fn stderr() -> Stderr {
+    Stderr(())
+}
+
+impl Write for Stderr {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        STDERR.with(|output| {
+            output.write(buffer, false)?;
+            output.flush()?;
+            Ok(buffer.len())
+        })
+    }
+
+    /// Returns early if another fiber is already flushing, it writes out everything that's buffered before finishing.
+    fn flush(&mut self) -> io::Result<()> {
+        STDERR.with(|output| output.flush())
+    }
+}
+
+impl AsRawFd for Stderr {
+    fn as_raw_fd(&self) -> RawFd {
+        libc::STDERR_FILENO
+    }
+}
+
+/// Handle to the standard input, see [stdin].
+#[derive(Debug, Copy, Clone)]
+pub struct Stdin(());
+
+/// Returns a handle to the standard input.
+///
+/// The buffer is shared by every handle on the thread.
+pub fn stdin() -> Stdin {
+    Stdin(())
+}
+
+impl Stdin {
+    /// Reads until a newline, which is included, or end of file.
+    /// Returns the number of bytes appended to the string.
+    pub fn read_line(&mut self, line: &mut String) -> io::Result<usize> {
+        let mut bytes = Vec::new();
+
+        loop {
+            let mut buffered = self.take_buffered()?;
+            if buffered.is_empty() {
+                break; // end of file
+            }
+
+            match buffered.iter().position(|&byte| byte == b'\n') {
+                Some(newline) => {
+                    let rest = buffered.split_off(newline + 1);
+                    bytes.append(&mut buffered);
+                    unread(rest);
+                    break;
+                }
+                None => bytes.append(&mut buffered),
+            }
+        }
+
+        let string = String::from_utf8(bytes)
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+        line.push_str(&string);
+        Ok(string.len())
+    }
+
+    /// Iterates over the lines of the standard input, without their newlines.
+    pub fn lines(self) -> impl Iterator<Item = io::Result<String>> {
+        let mut stdin = self;
+        std::iter::from_fn(move || {
+            let mut line = String::new();
+            match stdin.read_line(&mut line) {
+                Ok(0) => None,
+                Ok(_) => {
+                    if line.ends_with('\n') {
+                        line.pop();
+                    }
+                    Some(Ok(line))
+                }
+                Err(error) => Some(Err(error)),
+            }
+        })
+    }
+
+    /// Takes the buffered input, reading more if there isn't any.
+    /// Only empty at end of file.
+    fn take_buffered(&mut self) -> io::Result<Vec<u8>> {
+        let buffered = STDIN.with(|stdin| mem::take(&mut *stdin.borrow_mut()));
+        if !buffered.is_empty() {
+            return Ok(buffered);
+        }
+
+        let mut buffer = vec![0; READ_CAPACITY];
+        let bytes_read = read(libc::STDIN_FILENO, &mut buffer)?;
+        buffer.truncate(bytes_read);
+        Ok(buffer)
+    }
+}
+
+impl Read for Stdin {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        let mut buffered = self.take_buffered()?;
+
+        let length = cmp::min(buffer.len(), buffered.len());
+        buffer[..length].copy_from_slice(&buffered[..length]);
+        let rest = buffered.split_off(length);
+        unread(rest);
+
+        Ok(length)
+    }
+}
+
+/// Puts input back in front of the buffer, for the next read.
+fn unread(mut input: Vec<u8>) {
+    STDIN.with(|stdin| {
+        let mut buffered = stdin.borrow_mut();
+        input.append(&mut buffered);
+        *buffered = input;
+    });
+}
+
+impl AsRawFd for Stdin {
+    fn as_raw_fd(&self) -> RawFd {
+        libc::STDIN_FILENO
+    }
+}
+
+/// File descriptor that's closed when dropped, without blocking other fibers.
+#[derive(Debug)]
+pub(crate) struct Fd(pub(crate) RawFd);
+
+impl Drop for Fd {
+    fn drop(&mut self) {
+        let fd = io_uring::types::Fd(self.0);
+        let sqe = io_uring::opcode::Close::new(fd).build();
+        let fallback = || unsafe { libc::close(fd.0) } as i64;
+        let opcode = io_uring::opcode::Close::CODE;
+        // don't leak when cancelled
+        let _ = runtime::shield(|| runtime::syscall_or_unblock(opcode, sqe, fallback));
+    }
+}
+
+/// Reads from any kind of file descriptor, advancing its offset if it supports seeking.
+pub(crate) fn read(fd: RawFd, buffer: &mut [u8]) -> io::Result<usize> {
+    let fd = io_uring::types::Fd(fd);
+    let length = cmp::min(buffer.len(), u32::MAX as usize) as u32;
+    let sqe = io_uring::opcode::Read::new(fd, buffer.as_mut_ptr(), length)
+        .offset(CURRENT_POSITION)
+        .build();
+    let fallback =
+        || unsafe { libc::read(fd.0, buffer.as_mut_ptr().cast(), length as usize) } as i64;
+    let opcode = io_uring::opcode::Read::CODE;
+    let bytes_read = runtime::syscall_or_unblock(opcode, sqe, fallback)?;
+    Ok(bytes_read as usize)
+}
+
+/// Writes to any kind of file descriptor, advancing its offset if it supports seeking.
+pub(crate) fn write(fd: RawFd, buffer: &[u8]) -> io::Result<usize> {
+    let fd = io_uring::types::Fd(fd);
+    let length = cmp::min(buffer.len(), u32::MAX as usize) as u32;
+    let sqe = io_uring::opcode::Write::new(fd, buffer.as_ptr(), length)
+        .offset(CURRENT_POSITION)
+        .build();
+    let fallback = || unsafe { libc::write(fd.0, buffer.as_ptr().cast(), length as usize) } as i64;
+    let opcode = io_uring::opcode::Write::CODE;
+    let bytes_wrote = runtime::syscall_or_unblock(opcode, sqe, fallback)?;
+    Ok(bytes_wrote as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::runtime::{spawn, start};
+
+    use super::*;
+
+    #[test]
+    fn pipes_data() {
+        start(|| {
+            let (mut reader, mut writer) = pipe().unwrap();
+
+            writer.write_all(b"hello").unwrap();
+            drop(writer);
+
+            let mut output = String::new();
+            reader.read_to_string(&mut output).unwrap();
+            assert_eq!(output, "hello");
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn reader_waits_for_writer() {
+        start(|| {
+            let (mut reader, mut writer) = pipe().unwrap();
+
+            spawn(move || {
+                crate::time::sleep(std::time::Duration::from_millis(1)).unwrap();
+                writer.write_all(b"hello").unwrap();
+            });
+
+            let mut buffer = [0; 5];
+            reader.read_exact(&mut buffer).unwrap();
+            assert_eq!(&buffer, b"hello");
+        })
+        .unwrap();
+    }
+
+    mod copy {
+        use super::*;
+
+        #[test]
+        fn splices_between_files() {
+            start(|| {
+                let path = format!("/tmp/{}", uuid::Uuid::new_v4());
+                let reader = crate::fs::File::open("/etc/hosts").unwrap();
+                let writer = crate::fs::File::create(&path).unwrap();
+
+                let copied = copy(&reader, &writer).unwrap();
+
+                let expected = crate::fs::read("/etc/hosts").unwrap();
+                assert_eq!(copied, expected.len() as u64);
+                assert_eq!(crate::fs::read(&path).unwrap(), expected);
+            })
+            .unwrap();
+        }
+
+        #[test]
+        fn falls_back_for_append_only_writer() {
+            start(|| {
+                let path = format!("/tmp/{}", uuid::Uuid::new_v4());
+                crate::fs::write(&path, b"hosts: ").unwrap();
+                let reader = crate::fs::File::open("/etc/hosts").unwrap();
+                let writer = crate::fs::File::options().append(true).open(&path).unwrap();
+
+                copy(&reader, &writer).unwrap();
+
+                let mut expected = b"hosts: ".to_vec();
+                expected.extend(crate::fs::read("/etc/hosts").unwrap());
+                assert_eq!(crate::fs::read(&path).unwrap(), expected);
+            })
+            .unwrap();
+        }
+
+        #[test]
+        fn falls_back_without_splice() {
+            start(|| {
+                runtime::pretend_unsupported(io_uring::opcode::Splice::CODE);
+                let (reader, mut writer) = pipe().unwrap();
+                let (mut output, output_writer) = pipe().unwrap();
+                writer.write_all(b"hello").unwrap();
+                drop(writer);
+
+                assert_eq!(copy(&reader, &output_writer).unwrap(), 5);
+
+                drop(output_writer);
+                let mut buffer = String::new();
+                output.read_to_string(&mut buffer).unwrap();
+                assert_eq!(buffer, "hello");
+            })
+            .unwrap();
+        }
+
+        #[test]
+        fn stops_when_cancelled() {
+            start(|| {
+                let (reader, writer) = pipe().unwrap();
+                let (_output, output_writer) = pipe().unwrap();
+
+                let handle = spawn(move || copy(&reader, &output_writer));
+                crate::time::sleep(std::time::Duration::from_millis(1)).unwrap();
+                handle.cancel();
+
+                assert!(matches!(
+                    handle.join().unwrap(),
+                    Err(crate::Error::Cancelled(_))
+                ));
+                drop(writer);
+            })
+            .unwrap();
+        }
+
+        #[test]
+        fn copies_both_directions() {
+            start(|| {
+                let (a, a_peer) = std::os::unix::net::UnixStream::pair().unwrap();
+                let (b, b_peer) = std::os::unix::net::UnixStream::pair().unwrap();
+
+                let handle = spawn(move || copy_bidirectional(&a, &b).unwrap());
+
+                // peers use blocking std sockets, so they run on threads
+                let a_side = std::thread::spawn(move || {
+                    let mut a_peer = a_peer;
+                    a_peer.write_all(b"ping").unwrap();
+                    a_peer.shutdown(std::net::Shutdown::Write).unwrap();
+                    let mut buffer = String::new();
+                    a_peer.read_to_string(&mut buffer).unwrap();
+                    buffer
+                });
+                let b_side = std::thread::spawn(move || {
+                    let mut b_peer = b_peer;
+                    b_peer.write_all(b"pong").unwrap();
+                    b_peer.shutdown(std::net::Shutdown::Write).unwrap();
+                    let mut buffer = String::new();
+                    b_peer.read_to_string(&mut buffer).unwrap();
+                    buffer
+                });
+
+                assert_eq!(handle.join().unwrap(), (4, 4));
+                assert_eq!(a_side.join().unwrap(), "pong");
+                assert_eq!(b_side.join().unwrap(), "ping");
+            })
+            .unwrap();
+        }
+
+        #[test]
+        fn stops_both_directions_when_cancelled() {
+            start(|| {
+                let (a, _a_peer) = std::os::unix::net::UnixStream::pair().unwrap();
+                let (b, _b_peer) = std::os::unix::net::UnixStream::pair().unwrap();
+
+                let handle = spawn(move || copy_bidirectional(&a, &b));
+                crate::time::sleep(std::time::Duration::from_millis(1)).unwrap();
+                handle.cancel_with(crate::CancellationReason::Shutdown);
+
+                let result = handle.join().unwrap();
+                let reason = crate::CancellationReason::Shutdown;
+                assert!(matches!(result, Err(crate::Error::Cancelled(r)) if r == reason));
+            })
+            .unwrap();
+        }
+    }
+
+    #[test]
+    fn line_buffers_output() {
+        start(|| {
+            let (mut reader, writer) = pipe().unwrap();
+            let output = Output::new(writer.as_raw_fd());
+
+            output.write(b"hello", true).unwrap();
+            assert_eq!(output.buffer.borrow().len(), 5);
+
+            output.write(b" world\n", true).unwrap();
+            assert!(output.buffer.borrow().is_empty());
+
+            let mut buffer = [0; 12];
+            reader.read_exact(&mut buffer).unwrap();
+            assert_eq!(&buffer, b"hello world\n");
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn prints() {
+        start(|| {
+            crate::print!("printed ");
+            crate::println!("by {}", "uringy");
+            crate::eprintln!("to stderr");
+            stdout().flush().unwrap();
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn prints_outside_runtime() {
+        crate::println!("printed without {}", "a runtime");
+        crate::eprintln!("to stderr without a runtime");
+
+        start(|| {
+            runtime::unblock(|| crate::println!("printed from the helper thread"));
+        })
+        .unwrap();
+    }
+}
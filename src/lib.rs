@@ -1,12 +1,14 @@
 #![cfg_attr(feature = "fast_thread_local", feature(thread_local))]
 
 #[cfg(feature = "macros")]
-pub use uringy_macros::start;
+pub use uringy_macros::{start, test};
 
 pub mod circular_buffer;
 pub mod ecosystem;
 pub mod fs;
+pub mod io;
 pub mod net;
+pub mod process;
 pub mod runtime;
 pub mod sync;
 pub mod time;
@@ -17,8 +19,8 @@ pub enum Error<E> {
     #[error("original...")]
     Original(#[from] E),
 
-    #[error("cancelled...")]
-    Cancelled,
+    #[error("cancelled: {0}")]
+    Cancelled(CancellationReason),
 }
 
 impl<E> Error<E> {
@@ -27,7 +29,7 @@ impl<E> Error<E> {
     pub fn map<F: FnOnce(E) -> U, U>(self, f: F) -> Error<U> {
         match self {
             Error::Original(e) => Error::Original(f(e)),
-            Error::Cancelled => Error::Cancelled,
+            Error::Cancelled(reason) => Error::Cancelled(reason),
         }
     }
 
@@ -36,7 +38,7 @@ impl<E> Error<E> {
     pub fn and_then<F: FnOnce(E) -> Error<U>, U>(self, f: F) -> Error<U> {
         match self {
             Error::Original(e) => f(e),
-            Error::Cancelled => Error::Cancelled,
+            Error::Cancelled(reason) => Error::Cancelled(reason),
         }
     }
 }
@@ -44,8 +46,8 @@ impl<E> Error<E> {
 impl Error<std::io::Error> {
     /// ...
     pub fn from_io_error(error: std::io::Error) -> Self {
-        match error.raw_os_error().unwrap() {
-            libc::ECANCELED => Error::Cancelled,
+        match error.raw_os_error() {
+            Some(libc::ECANCELED) => crate::runtime::cancelled(),
             _ => Error::Original(error),
         }
     }
@@ -55,7 +57,38 @@ impl From<Error<std::io::Error>> for std::io::Error {
     fn from(error: Error<std::io::Error>) -> Self {
         match error {
             Error::Original(e) => e,
-            Error::Cancelled => std::io::Error::from_raw_os_error(libc::ECANCELED),
+            Error::Cancelled(_) => std::io::Error::from_raw_os_error(libc::ECANCELED),
+        }
+    }
+}
+
+/// Why a fiber was cancelled, see [runtime::cancel_with].
+///
+/// Propagates to every descendant of the cancelled fiber.
+/// The first reason a fiber is cancelled with sticks.
+#[non_exhaustive]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CancellationReason {
+    /// Cancelled without a more specific reason, e.g. by [runtime::cancel].
+    Requested,
+    /// Took longer than it was allowed to.
+    Timeout,
+    /// The application is gracefully shutting down.
+    Shutdown,
+    /// A fiber that wasn't joined panicked, cancelling its nearest contained ancestor.
+    Panicked,
+    /// Application specific reason.
+    Custom(&'static str),
+}
+
+impl std::fmt::Display for CancellationReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CancellationReason::Requested => write!(f, "requested"),
+            CancellationReason::Timeout => write!(f, "timed out"),
+            CancellationReason::Shutdown => write!(f, "shutting down"),
+            CancellationReason::Panicked => write!(f, "a fiber panicked"),
+            CancellationReason::Custom(reason) => write!(f, "{reason}"),
         }
     }
 }
@@ -32,7 +32,17 @@ impl Write for WriteHalf {
     fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
         let fd = io_uring::types::Fd(self.0.borrow().fd);
         let sqe = io_uring::opcode::Send::new(fd, buffer.as_ptr(), buffer.len() as u32).build();
-        let bytes_wrote = runtime::syscall(sqe)?;
+        let fallback = || unsafe {
+            libc::send(
+                fd.0,
+                buffer.as_ptr().cast(),
+                buffer.len(),
+                libc::MSG_DONTWAIT,
+            ) as i64
+        };
+        let opcode = io_uring::opcode::Send::CODE;
+        let events = libc::POLLOUT as u32;
+        let bytes_wrote = runtime::syscall_or_poll(opcode, sqe, fd.0, events, fallback)?;
         Ok(bytes_wrote as usize)
     }
 
@@ -48,7 +58,17 @@ impl Read for ReadHalf {
     fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
         let fd = io_uring::types::Fd(self.0.borrow().fd);
         let sqe = io_uring::opcode::Recv::new(fd, buffer.as_mut_ptr(), buffer.len() as u32).build();
-        let bytes_read = runtime::syscall(sqe)?;
+        let fallback = || unsafe {
+            libc::recv(
+                fd.0,
+                buffer.as_mut_ptr().cast(),
+                buffer.len(),
+                libc::MSG_DONTWAIT,
+            ) as i64
+        };
+        let opcode = io_uring::opcode::Recv::CODE;
+        let events = libc::POLLIN as u32;
+        let bytes_read = runtime::syscall_or_poll(opcode, sqe, fd.0, events, fallback)?;
         Ok(bytes_read as usize)
     }
 }
@@ -58,6 +78,17 @@ struct StreamState {
     fd: RawFd,
 }
 
+impl Drop for StreamState {
+    fn drop(&mut self) {
+        let fd = io_uring::types::Fd(self.fd);
+        let sqe = io_uring::opcode::Close::new(fd).build();
+        let fallback = || unsafe { libc::close(fd.0) } as i64;
+        let opcode = io_uring::opcode::Close::CODE;
+        // don't leak when cancelled
+        let _ = runtime::shield(|| runtime::syscall_or_unblock(opcode, sqe, fallback));
+    }
+}
+
 /// ...
 #[derive(Debug)]
 pub struct Listener(RawFd);
@@ -130,7 +161,10 @@ impl Drop for Listener {
     fn drop(&mut self) {
         let fd = io_uring::types::Fd(self.0);
         let sqe = io_uring::opcode::Close::new(fd).build();
-        let _ = runtime::syscall(sqe);
+        let fallback = || unsafe { libc::close(fd.0) } as i64;
+        let opcode = io_uring::opcode::Close::CODE;
+        // don't leak when cancelled
+        let _ = runtime::shield(|| runtime::syscall_or_unblock(opcode, sqe, fallback));
     }
 }
 
@@ -213,6 +247,32 @@ mod tests {
         .unwrap();
     }
 
+    #[test]
+    fn falls_back_to_polling() {
+        start(|| {
+            runtime::pretend_unsupported(io_uring::opcode::Send::CODE);
+            runtime::pretend_unsupported(io_uring::opcode::Recv::CODE);
+            runtime::pretend_unsupported(io_uring::opcode::Close::CODE);
+            let listener = Listener::bind((Ipv4Addr::UNSPECIFIED, 0)).unwrap();
+            let server_addr = listener.local_addr().unwrap();
+
+            spawn(move || {
+                let ((mut w, mut r), _) = listener.accept().unwrap();
+                let mut buffer = vec![0; 1024];
+                let bytes_read = r.read(&mut buffer).unwrap();
+                w.write_all(&buffer[..bytes_read]).unwrap();
+            });
+
+            let (mut w, mut r) = connect((Ipv4Addr::LOCALHOST, server_addr.port())).unwrap();
+            w.write_all(b"hello").unwrap();
+
+            let mut buffer = vec![0; 1024];
+            let bytes_read = r.read(&mut buffer).unwrap();
+            assert_eq!(&buffer[..bytes_read], b"hello");
+        })
+        .unwrap();
+    }
+
     // #[test]
     // // #[ignore = "takes 16s to run in release mode"]
     // fn cleans_up_after_itself() {
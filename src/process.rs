@@ -0,0 +1,694 @@
+//! Child processes inspired by the standard library.
+//!
+//! Waiting for a child parks the fiber on the child's pidfd, so other fibers keep running.
+
+use std::ffi::{CString, OsStr, OsString};
+use std::io::{Read, Write};
+use std::os::fd::{AsRawFd, RawFd};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::process::{ExitStatus, Output};
+use std::{io, mem, ptr};
+
+use crate::io::Fd;
+use crate::runtime;
+
+/// Builder for spawning a child process.
+#[derive(Debug)]
+pub struct Command {
+    program: OsString,
+    args: Vec<OsString>,
+    env: Vec<(OsString, Option<OsString>)>,
+    current_dir: Option<PathBuf>,
+    stdin: Option<Stdio>,
+    stdout: Option<Stdio>,
+    stderr: Option<Stdio>,
+}
+
+impl Command {
+    /// Creates a command for running the program, which is searched for in `PATH` unless it's a path.
+    pub fn new(program: impl AsRef<OsStr>) -> Self {
+        Command {
+            program: program.as_ref().to_owned(),
+            args: Vec::new(),
+            env: Vec::new(),
+            current_dir: None,
+            stdin: None,
+            stdout: None,
+            stderr: None,
+        }
+    }
+
+    /// Adds an argument to pass to the program.
+    pub fn arg(&mut self, arg: impl AsRef<OsStr>) -> &mut Self {
+        self.args.push(arg.as_ref().to_owned());
+        self
+    }
+
+    /// Adds multiple arguments to pass to the program.
+    pub fn args<I: IntoIterator<Item = S>, S: AsRef<OsStr>>(&mut self, args: I) -> &mut Self {
+        for arg in args {
+            self.arg(arg);
+        }
+        self
+    }
+
+    /// Sets an environment variable for the child, on top of the ones inherited from this process.
+    pub fn env(&mut self, key: impl AsRef<OsStr>, value: impl AsRef<OsStr>) -> &mut Self {
+        let value = Some(value.as_ref().to_owned());
+        self.env.push((key.as_ref().to_owned(), value));
+        self
+    }
+
+    /// Stops the child from inheriting an environment variable.
+    pub fn env_remove(&mut self, key: impl AsRef<OsStr>) -> &mut Self {
+        self.env.push((key.as_ref().to_owned(), None));
+        self
+    }
+
+    /// Sets the working directory of the child.
+    pub fn current_dir(&mut self, dir: impl AsRef<Path>) -> &mut Self {
+        self.current_dir = Some(dir.as_ref().to_owned());
+        self
+    }
+
+    /// Configures the child's standard input, inherited by default.
+    pub fn stdin(&mut self, stdin: Stdio) -> &mut Self {
+        self.stdin = Some(stdin);
+        self
+    }
+
+    /// Configures the child's standard output, inherited by default.
+    pub fn stdout(&mut self, stdout: Stdio) -> &mut Self {
+        self.stdout = Some(stdout);
+        self
+    }
+
+    /// Configures the child's standard error, inherited by default.
+    pub fn stderr(&mut self, stderr: Stdio) -> &mut Self {
+        self.stderr = Some(stderr);
+        self
+    }
+
+    /// Spawns the child process.
+    pub fn spawn(&mut self) -> crate::IoResult<Child> {
+        self.spawn_with(Stdio::Inherit, Stdio::Inherit, Stdio::Inherit)
+    }
+
+    /// Spawns the child process, waits for it to exit, and collects its standard output and error.
+    /// Standard input is [Stdio::Null] unless configured otherwise.
+    ///
+    /// The child is killed if the fiber is cancelled in the meantime.
+    pub fn output(&mut self) -> crate::IoResult<Output> {
+        let child = self.spawn_with(Stdio::Null, Stdio::Piped, Stdio::Piped)?;
+        let mut guard = OutputGuard {
+            child,
+            stderr: None,
+        };
+        let mut stdout = guard.child.stdout.take();
+        let mut stderr = guard.child.stderr.take();
+
+        // read concurrently, otherwise the child could block on a full pipe
+        guard.stderr = Some(runtime::spawn(move || {
+            let mut buffer = Vec::new();
+            if let Some(stderr) = &mut stderr {
+                stderr.read_to_end(&mut buffer)?;
+            }
+            Ok::<_, io::Error>(buffer)
+        }));
+
+        let mut stdout_buffer = Vec::new();
+        if let Some(stdout) = &mut stdout {
+            stdout
+                .read_to_end(&mut stdout_buffer)
+                .map_err(crate::Error::from_io_error)?;
+        }
+
+        let stderr = match guard.stderr.take().unwrap().join() {
+            Ok(result) => result.map_err(crate::Error::from_io_error)?,
+            Err(crate::Error::Cancelled(reason)) => return Err(crate::Error::Cancelled(reason)),
+            Err(crate::Error::Original(payload)) => std::panic::resume_unwind(payload),
+        };
+
+        Ok(Output {
+            status: guard.child.wait()?,
+            stdout: stdout_buffer,
+            stderr,
+        })
+    }
+
+    /// Spawns the child process, waits for it to exit, and returns its exit status.
+    ///
+    /// The child is killed if the fiber is cancelled in the meantime.
+    pub fn status(&mut self) -> crate::IoResult<ExitStatus> {
+        self.spawn()?.wait()
+    }
+
+    fn spawn_with(&mut self, stdin: Stdio, stdout: Stdio, stderr: Stdio) -> crate::IoResult<Child> {
+        let program = cstring(&self.program)?;
+        let mut argv = vec![program.clone()];
+        for arg in &self.args {
+            argv.push(cstring(arg)?);
+        }
+
+        let mut envp = vec![];
+        for (key, value) in std::env::vars_os() {
+            if self.env.iter().any(|(k, _)| *k == key) {
+                continue; // overridden
+            }
+            envp.push(env_entry(&key, &value)?);
+        }
+        for (i, (key, value)) in self.env.iter().enumerate() {
+            let is_latest = !self.env[i + 1..].iter().any(|(k, _)| k == key);
+            if let (Some(value), true) = (value, is_latest) {
+                envp.push(env_entry(key, value)?);
+            }
+        }
+
+        let current_dir = self.current_dir.as_ref().map(cstring).transpose()?;
+
+        let stdin = self.stdin.unwrap_or(stdin).pipe(true)?;
+        let stdout = self.stdout.unwrap_or(stdout).pipe(false)?;
+        let stderr = self.stderr.unwrap_or(stderr).pipe(false)?;
+
+        let pid = unsafe {
+            spawn(
+                &program,
+                &argv,
+                &envp,
+                current_dir.as_ref(),
+                [&stdin, &stdout, &stderr],
+            )
+        }?;
+
+        // can't race with the pid being reused, since only this process can reap the child
+        let pidfd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid, 0) };
+        if pidfd == -1 {
+            let error = io::Error::last_os_error();
+            unsafe { libc::kill(pid, libc::SIGKILL) };
+            unsafe { libc::waitpid(pid, ptr::null_mut(), 0) };
+            return Err(crate::Error::Original(error));
+        }
+
+        Ok(Child {
+            pid,
+            pidfd: pidfd as RawFd,
+            status: None,
+            stdin: stdin.parent.map(ChildStdin),
+            stdout: stdout.parent.map(ChildStdout),
+            stderr: stderr.parent.map(ChildStderr),
+        })
+    }
+}
+
+/// How a child's standard stream is set up.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Stdio {
+    /// Shares the stream with this process.
+    Inherit,
+    /// Connects the stream to a pipe, accessible through [Child].
+    Piped,
+    /// Connects the stream to `/dev/null`.
+    Null,
+}
+
+/// Both ends of a child's standard stream.
+struct Pipe {
+    parent: Option<Fd>,
+    child: Option<Fd>,
+}
+
+impl Stdio {
+    fn pipe(self, is_input: bool) -> crate::IoResult<Pipe> {
+        match self {
+            Stdio::Inherit => Ok(Pipe {
+                parent: None,
+                child: None,
+            }),
+            Stdio::Piped => {
+                let mut fds = [0; 2];
+                if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } == -1 {
+                    return Err(crate::Error::Original(io::Error::last_os_error()));
+                }
+                let [read, write] = fds.map(Fd);
+
+                let (parent, child) = if is_input {
+                    (write, read)
+                } else {
+                    (read, write)
+                };
+                Ok(Pipe {
+                    parent: Some(parent),
+                    child: Some(child),
+                })
+            }
+            Stdio::Null => {
+                let path = b"/dev/null\0".as_ptr().cast();
+                let flags = libc::O_CLOEXEC
+                    | if is_input {
+                        libc::O_RDONLY
+                    } else {
+                        libc::O_WRONLY
+                    };
+                let fd = unsafe { libc::open(path, flags) };
+                if fd == -1 {
+                    return Err(crate::Error::Original(io::Error::last_os_error()));
+                }
+                Ok(Pipe {
+                    parent: None,
+                    child: Some(Fd(fd)),
+                })
+            }
+        }
+    }
+}
+
+/// Spawns the program with `posix_spawn`, which uses `vfork` so it's cheap even for large processes.
+unsafe fn spawn(
+    program: &CString,
+    argv: &[CString],
+    envp: &[CString],
+    current_dir: Option<&CString>,
+    stdio: [&Pipe; 3],
+) -> crate::IoResult<libc::pid_t> {
+    let mut actions = mem::MaybeUninit::uninit();
+    cvt(libc::posix_spawn_file_actions_init(actions.as_mut_ptr()))?;
+    let mut actions = FileActions(actions.assume_init());
+
+    for (target, pipe) in stdio.into_iter().enumerate() {
+        if let Some(child) = &pipe.child {
+            // duplicates don't inherit O_CLOEXEC
+            cvt(libc::posix_spawn_file_actions_adddup2(
+                &mut actions.0,
+                child.0,
+                target as i32,
+            ))?;
+        }
+    }
+
+    if let Some(current_dir) = current_dir {
+        cvt(libc::posix_spawn_file_actions_addchdir_np(
+            &mut actions.0,
+            current_dir.as_ptr(),
+        ))?;
+    }
+
+    let mut attributes = mem::MaybeUninit::uninit();
+    cvt(libc::posix_spawnattr_init(attributes.as_mut_ptr()))?;
+    let mut attributes = Attributes(attributes.assume_init());
+
+    // Rust ignores SIGPIPE, which would otherwise be inherited
+    let mut default_signals = mem::MaybeUninit::uninit();
+    libc::sigemptyset(default_signals.as_mut_ptr());
+    libc::sigaddset(default_signals.as_mut_ptr(), libc::SIGPIPE);
+    cvt(libc::posix_spawnattr_setsigdefault(
+        &mut attributes.0,
+        default_signals.as_ptr(),
+    ))?;
+
+    let mut mask = mem::MaybeUninit::uninit();
+    libc::sigemptyset(mask.as_mut_ptr());
+    cvt(libc::posix_spawnattr_setsigmask(
+        &mut attributes.0,
+        mask.as_ptr(),
+    ))?;
+
+    let flags = libc::POSIX_SPAWN_SETSIGDEF | libc::POSIX_SPAWN_SETSIGMASK;
+    cvt(libc::posix_spawnattr_setflags(
+        &mut attributes.0,
+        flags as libc::c_short,
+    ))?;
+
+    let argv = null_terminated(argv);
+    let envp = null_terminated(envp);
+
+    let mut pid = 0;
+    cvt(libc::posix_spawnp(
+        &mut pid,
+        program.as_ptr(),
+        &actions.0,
+        &attributes.0,
+        argv.as_ptr().cast(),
+        envp.as_ptr().cast(),
+    ))?;
+
+    Ok(pid)
+}
+
+struct FileActions(libc::posix_spawn_file_actions_t);
+
+impl Drop for FileActions {
+    fn drop(&mut self) {
+        unsafe { libc::posix_spawn_file_actions_destroy(&mut self.0) };
+    }
+}
+
+struct Attributes(libc::posix_spawnattr_t);
+
+impl Drop for Attributes {
+    fn drop(&mut self) {
+        unsafe { libc::posix_spawnattr_destroy(&mut self.0) };
+    }
+}
+
+/// posix_spawn functions return the error number rather than setting errno.
+fn cvt(result: libc::c_int) -> crate::IoResult<()> {
+    match result {
+        0 => Ok(()),
+        error => Err(crate::Error::Original(io::Error::from_raw_os_error(error))),
+    }
+}
+
+fn null_terminated(strings: &[CString]) -> Vec<*const libc::c_char> {
+    let pointers = strings.iter().map(|string| string.as_ptr());
+    pointers.chain([ptr::null()]).collect()
+}
+
+fn cstring(string: impl AsRef<OsStr>) -> crate::IoResult<CString> {
+    let bytes = string.as_ref().as_bytes();
+    CString::new(bytes).map_err(|error| crate::Error::Original(error.into()))
+}
+
+fn env_entry(key: &OsStr, value: &OsStr) -> crate::IoResult<CString> {
+    let mut entry = key.to_owned();
+    entry.push("=");
+    entry.push(value);
+    cstring(entry)
+}
+
+/// Handle to a running or exited child process.
+#[derive(Debug)]
+pub struct Child {
+    pid: libc::pid_t,
+    pidfd: RawFd,
+    status: Option<ExitStatus>,
+    /// Writes to the child's standard input, if it was [Stdio::Piped].
+    pub stdin: Option<ChildStdin>,
+    /// Reads from the child's standard output, if it was [Stdio::Piped].
+    pub stdout: Option<ChildStdout>,
+    /// Reads from the child's standard error, if it was [Stdio::Piped].
+    pub stderr: Option<ChildStderr>,
+}
+
+impl Child {
+    /// The child's process id.
+    pub fn id(&self) -> u32 {
+        self.pid as u32
+    }
+
+    /// Sends `SIGKILL` to the child, unless it was already waited on.
+    pub fn kill(&mut self) -> crate::IoResult<()> {
+        if self.status.is_some() {
+            return Ok(());
+        }
+
+        // unlike kill(2), a pidfd can't refer to a reused pid
+        let result = unsafe {
+            libc::syscall(
+                libc::SYS_pidfd_send_signal,
+                self.pidfd,
+                libc::SIGKILL,
+                ptr::null::<libc::siginfo_t>(),
+                0,
+            )
+        };
+        if result == -1 {
+            return Err(crate::Error::Original(io::Error::last_os_error()));
+        }
+
+        Ok(())
+    }
+
+    /// Waits for the child to exit, closing its standard input first so it doesn't wait for more input.
+    ///
+    /// The child is killed if the fiber is cancelled.
+    pub fn wait(&mut self) -> crate::IoResult<ExitStatus> {
+        drop(self.stdin.take());
+
+        if let Some(status) = self.status {
+            return Ok(status);
+        }
+
+        // pidfd becomes readable once the child exits
+        let fd = io_uring::types::Fd(self.pidfd);
+        let sqe = io_uring::opcode::PollAdd::new(fd, libc::POLLIN as u32).build();
+        match runtime::syscall(sqe) {
+            Ok(_) => {}
+            Err(crate::Error::Cancelled(reason)) => {
+                let _ = self.kill();
+                runtime::shield(|| self.wait())?;
+                return Err(crate::Error::Cancelled(reason));
+            }
+            Err(error) => return Err(error),
+        }
+
+        let mut status = 0;
+        let result = unsafe { libc::waitpid(self.pid, &mut status, libc::WNOHANG) };
+        if result == -1 {
+            return Err(crate::Error::Original(io::Error::last_os_error()));
+        }
+        assert_eq!(result, self.pid);
+
+        let status = ExitStatus::from_raw(status);
+        self.status = Some(status);
+        Ok(status)
+    }
+
+    /// Returns the exit status if the child has exited, without waiting.
+    pub fn try_wait(&mut self) -> crate::IoResult<Option<ExitStatus>> {
+        if let Some(status) = self.status {
+            return Ok(Some(status));
+        }
+
+        let mut status = 0;
+        let result = unsafe { libc::waitpid(self.pid, &mut status, libc::WNOHANG) };
+        match result {
+            -1 => Err(crate::Error::Original(io::Error::last_os_error())),
+            0 => Ok(None),
+            _ => {
+                let status = ExitStatus::from_raw(status);
+                self.status = Some(status);
+                Ok(Some(status))
+            }
+        }
+    }
+}
+
+impl Drop for Child {
+    fn drop(&mut self) {
+        // fiber might be dropping the child while unwinding from cancellation
+        if self.status.is_none() && runtime::is_cancelled() {
+            let _ = self.kill();
+            let _ = runtime::shield(|| self.wait());
+        }
+
+        unsafe { libc::close(self.pidfd) };
+    }
+}
+
+/// Cleans up after [Command::output] returns early, so the child doesn't linger as a zombie.
+struct OutputGuard {
+    child: Child,
+    stderr: Option<runtime::JoinHandle<io::Result<Vec<u8>>>>, // taken once joined
+}
+
+impl Drop for OutputGuard {
+    fn drop(&mut self) {
+        if self.child.status.is_none() {
+            let _ = self.child.kill();
+            let _ = runtime::shield(|| self.child.wait());
+        }
+
+        if let Some(stderr) = self.stderr.take() {
+            stderr.cancel(); // the pipe could be held open by the child's own children
+            let _ = runtime::shield(|| stderr.join());
+        }
+    }
+}
+
+/// Writes to a child's standard input, the child sees end of file once it's dropped.
+#[derive(Debug)]
+pub struct ChildStdin(Fd);
+
+impl Write for ChildStdin {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        crate::io::write(self.0 .0, buffer)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl AsRawFd for ChildStdin {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0 .0
+    }
+}
+
+/// Reads from a child's standard output.
+#[derive(Debug)]
+pub struct ChildStdout(Fd);
+
+impl Read for ChildStdout {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        crate::io::read(self.0 .0, buffer)
+    }
+}
+
+impl AsRawFd for ChildStdout {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0 .0
+    }
+}
+
+/// Reads from a child's standard error.
+#[derive(Debug)]
+pub struct ChildStderr(Fd);
+
+impl Read for ChildStderr {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        crate::io::read(self.0 .0, buffer)
+    }
+}
+
+impl AsRawFd for ChildStderr {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0 .0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use crate::runtime::{spawn, start};
+
+    use super::*;
+
+    #[test]
+    fn collects_output() {
+        start(|| {
+            let output = Command::new("sh")
+                .args(["-c", "echo out; echo err >&2; exit 3"])
+                .output()
+                .unwrap();
+
+            assert_eq!(output.status.code(), Some(3));
+            assert_eq!(output.stdout, b"out\n");
+            assert_eq!(output.stderr, b"err\n");
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn pipes_stdin() {
+        start(|| {
+            let mut child = Command::new("cat")
+                .stdin(Stdio::Piped)
+                .stdout(Stdio::Piped)
+                .spawn()
+                .unwrap();
+
+            child.stdin.take().unwrap().write_all(b"hello").unwrap();
+            let mut output = String::new();
+            child
+                .stdout
+                .take()
+                .unwrap()
+                .read_to_string(&mut output)
+                .unwrap();
+
+            assert_eq!(output, "hello");
+            assert!(child.wait().unwrap().success());
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn sets_environment_and_directory() {
+        start(|| {
+            let output = Command::new("sh")
+                .args(["-c", "echo $GREETING; pwd"])
+                .env("GREETING", "hi")
+                .current_dir("/tmp")
+                .output()
+                .unwrap();
+
+            assert_eq!(output.stdout, b"hi\n/tmp\n");
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn doesnt_block_other_fibers() {
+        start(|| {
+            let before = Instant::now();
+            let handle = spawn(|| Command::new("sleep").arg("0.05").status().unwrap());
+
+            crate::time::sleep(Duration::from_millis(1)).unwrap();
+            assert!(before.elapsed() < Duration::from_millis(50));
+
+            assert!(handle.join().unwrap().success());
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn kills_child_when_cancelled() {
+        start(|| {
+            let before = Instant::now();
+            let handle = spawn(|| Command::new("sleep").arg("10").status());
+            crate::time::sleep(Duration::from_millis(5)).unwrap();
+
+            handle.cancel();
+
+            assert!(matches!(
+                handle.join().unwrap(),
+                Err(crate::Error::Cancelled(_))
+            ));
+            assert!(before.elapsed() < Duration::from_secs(10));
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn reaps_output_child_when_cancelled() {
+        start(|| {
+            let path = format!("/tmp/{}", uuid::Uuid::new_v4());
+            let script = format!("echo $$ > {path}; exec sleep 10");
+            let handle = spawn(move || Command::new("sh").args(["-c", &script]).output());
+            crate::time::sleep(Duration::from_millis(50)).unwrap();
+
+            handle.cancel();
+
+            assert!(matches!(
+                handle.join().unwrap(),
+                Err(crate::Error::Cancelled(_))
+            ));
+            let pid: libc::pid_t = std::fs::read_to_string(&path)
+                .unwrap()
+                .trim()
+                .parse()
+                .unwrap();
+            let result = unsafe { libc::waitpid(pid, ptr::null_mut(), libc::WNOHANG) };
+            assert_eq!(result, -1); // already reaped
+            std::fs::remove_file(path).unwrap();
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn fails_to_spawn_missing_program() {
+        start(|| {
+            let result = Command::new("/does/not/exist").spawn();
+
+            assert!(matches!(
+                result,
+                Err(crate::Error::Original(error)) if error.kind() == io::ErrorKind::NotFound
+            ));
+        })
+        .unwrap();
+    }
+}
@@ -0,0 +1,97 @@
+//! Helper thread for blocking syscalls that the kernel can't issue through io_uring.
+//!
+//! Jobs run one at a time, they only exist as a fallback for older kernels.
+
+use std::sync::{mpsc, Arc, Mutex};
+use std::{io, mem, thread};
+
+use super::{park_on, tls, SendWaker, WaitingOn};
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+#[derive(Debug)]
+pub(super) struct Helper {
+    jobs: Option<mpsc::Sender<Job>>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl Helper {
+    pub(super) fn new() -> io::Result<Self> {
+        let (jobs, receiver) = mpsc::channel::<Job>();
+        let thread = thread::Builder::new()
+            .name("uringy-blocking".to_string())
+            .spawn(move || receiver.into_iter().for_each(|job| job()))?;
+
+        Ok(Helper {
+            jobs: Some(jobs),
+            thread: Some(thread),
+        })
+    }
+
+    fn submit(&self, job: Job) {
+        self.jobs.as_ref().unwrap().send(job).unwrap();
+    }
+}
+
+impl Drop for Helper {
+    fn drop(&mut self) {
+        drop(self.jobs.take()); // stops the thread once it runs out of jobs
+        let _ = self.thread.take().unwrap().join();
+    }
+}
+
+/// Shared between a parked fiber and its job.
+struct Shared<T> {
+    output: Option<T>,
+    waker: Option<SendWaker>,
+}
+
+/// Runs the closure on the runtime's helper thread, parking until it returns.
+///
+/// Waits for the closure even when cancelled, which is what allows it to borrow from the fiber's stack.
+pub(crate) fn unblock<T: Send>(f: impl FnOnce() -> T + Send) -> T {
+    let shared = Arc::new(Mutex::new(Shared {
+        output: None,
+        waker: None,
+    }));
+
+    let job: Box<dyn FnOnce() + Send + '_> = Box::new({
+        let shared = shared.clone();
+        move || {
+            let output = f();
+
+            let mut shared = shared.lock().unwrap();
+            shared.output = Some(output);
+            if let Some(waker) = shared.waker.take() {
+                drop(shared);
+                waker.wake();
+            }
+        }
+    });
+    // safety: doesn't return until the job has run, so borrows outlive it
+    let job: Job = unsafe { mem::transmute(job) };
+
+    tls::runtime(|runtime| {
+        let helper = match &runtime.helper {
+            Some(helper) => helper,
+            None => runtime.helper.insert(Helper::new().unwrap()),
+        };
+        helper.submit(job);
+    });
+
+    loop {
+        // woken up by the job or cancellation
+        park_on(WaitingOn::Syscall, |waker| {
+            let mut shared = shared.lock().unwrap();
+            if shared.output.is_some() {
+                waker.schedule();
+            } else {
+                shared.waker = Some(waker.to_send()); // replaces stale waker from previous park
+            }
+        });
+
+        if let Some(output) = shared.lock().unwrap().output.take() {
+            return output;
+        }
+    }
+}
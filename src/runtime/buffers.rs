@@ -0,0 +1,110 @@
+//! Buffers registered with io_uring, so fixed reads and writes don't map their pages on every operation.
+//!
+//! The table is registered empty on first use, then filled in one slot at a time.
+//! Registrations belong to a runtime, buffers are registered again if they're used by another one.
+
+use std::cell::Cell;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use super::tls;
+
+/// Number of buffers that can be registered at once.
+const CAPACITY: u16 = 1024;
+
+/// Distinguishes the tables of runtimes that ran one after another on the same thread.
+static TABLE_IDS: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug)]
+pub(super) struct Table {
+    id: u64,
+    is_supported: Option<bool>, // registered on first use
+    free: Vec<u16>,
+    used: u16, // slots past this were never handed out
+}
+
+impl Table {
+    pub(super) fn new() -> Self {
+        Table {
+            id: TABLE_IDS.fetch_add(1, Ordering::Relaxed),
+            is_supported: None,
+            free: Vec::new(),
+            used: 0,
+        }
+    }
+}
+
+/// Slot of a registered buffer.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Registration {
+    table: u64,
+    index: u16,
+}
+
+/// Registers the buffer unless it's already registered with this runtime, returning its index for `ReadFixed` and `WriteFixed`.
+///
+/// Returns none if the kernel doesn't support it, or the table is full.
+/// The buffer has to stay allocated until it's unregistered.
+pub(crate) fn register_buffer(
+    registration: &Cell<Option<Registration>>,
+    buffer: *mut u8,
+    len: usize,
+) -> Option<u16> {
+    tls::runtime(|runtime| {
+        let table = &mut runtime.buffers;
+        let kernel = &runtime.kernel;
+
+        if let Some(registration) = registration.get() {
+            if registration.table == table.id {
+                return Some(registration.index);
+            }
+        }
+
+        let is_supported = *table
+            .is_supported
+            .get_or_insert_with(|| kernel.register_buffer_table(CAPACITY as u32).is_ok());
+        if !is_supported {
+            return None;
+        }
+
+        let index = match table.free.pop() {
+            Some(index) => index,
+            None if table.used < CAPACITY => {
+                table.used += 1;
+                table.used - 1
+            }
+            None => return None,
+        };
+
+        let iovec = libc::iovec {
+            iov_base: buffer.cast(),
+            iov_len: len,
+        };
+        if kernel.update_buffer(index, iovec).is_err() {
+            table.free.push(index); // e.g. over RLIMIT_MEMLOCK
+            return None;
+        }
+
+        registration.set(Some(Registration {
+            table: table.id,
+            index,
+        }));
+        Some(index)
+    })
+}
+
+/// Frees up the slot, unless the runtime it was registered with is gone.
+/// Operations that are still using the buffer keep it registered until they complete.
+pub(crate) fn unregister_buffer(registration: Registration) {
+    tls::try_runtime(|runtime| {
+        if registration.table != runtime.buffers.id {
+            return;
+        }
+
+        let iovec = libc::iovec {
+            iov_base: std::ptr::null_mut(),
+            iov_len: 0,
+        };
+        let _ = runtime.kernel.update_buffer(registration.index, iovec);
+        runtime.buffers.free.push(registration.index);
+    });
+}
@@ -0,0 +1,118 @@
+//! Simulated clock for deterministic tests, see [super::Builder::simulated_clock].
+//!
+//! Sleeping fibers are kept in a timer heap instead of the kernel.
+//! Time jumps straight to the next deadline once nothing else can make progress.
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::time::{Duration, Instant};
+
+use super::{park_on, tls, FiberIndex, WaitingOn, Waker};
+
+#[derive(Debug)]
+pub(super) struct Clock {
+    origin: Instant,
+    elapsed: Duration,
+    timers: BinaryHeap<Reverse<Timer>>,
+}
+
+/// Ordered by deadline, then by when the timer was created.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct Timer {
+    deadline: Duration,
+    park_token: u64,
+    fiber: FiberIndex,
+}
+
+impl Clock {
+    pub(super) fn new() -> Self {
+        Clock {
+            origin: Instant::now(),
+            elapsed: Duration::ZERO,
+            timers: BinaryHeap::new(),
+        }
+    }
+
+    pub(super) fn now(&self) -> Instant {
+        self.origin + self.elapsed
+    }
+
+    pub(super) fn has_timers(&self) -> bool {
+        !self.timers.is_empty()
+    }
+}
+
+impl super::RuntimeState {
+    /// Jumps to the earliest deadline, scheduling every fiber that's due.
+    /// Returns whether any fiber was scheduled.
+    pub(super) fn advance_clock(&mut self) -> bool {
+        let Some(clock) = &mut self.clock else {
+            return false;
+        };
+        let Some(Reverse(next)) = clock.timers.peek() else {
+            return false;
+        };
+        clock.elapsed = std::cmp::max(clock.elapsed, next.deadline);
+
+        let mut due = vec![];
+        while let Some(Reverse(timer)) = clock.timers.peek() {
+            if timer.deadline > clock.elapsed {
+                break;
+            }
+            due.push(clock.timers.pop().unwrap().0);
+        }
+
+        let mut is_scheduled = false;
+        for timer in due {
+            let Some(fiber) = self.fibers.get(timer.fiber.0) else {
+                continue; // completed since
+            };
+
+            // fiber might have been woken up by cancellation and parked on something else
+            if fiber.waiting_on == Some(WaitingOn::Sleep) && fiber.park_token == timer.park_token {
+                Waker(timer.fiber).schedule_with(self);
+                is_scheduled = true;
+            }
+        }
+
+        is_scheduled
+    }
+}
+
+/// Whether the runtime was started with a simulated clock.
+pub(crate) fn is_simulated() -> bool {
+    tls::runtime(|runtime| runtime.clock.is_some())
+}
+
+/// Parks until the simulated clock passes the duration, or the fiber is cancelled.
+pub(crate) fn sleep(duration: Duration) -> crate::CancellableResult<()> {
+    if super::is_cancelled() {
+        return Err(super::cancelled());
+    }
+
+    park_on(WaitingOn::Sleep, |waker| {
+        tls::runtime(|runtime| {
+            let park_token = runtime.fibers[waker.0 .0].park_token;
+            let clock = runtime.clock.as_mut().unwrap();
+            clock.timers.push(Reverse(Timer {
+                deadline: clock.elapsed + duration,
+                park_token,
+                fiber: waker.0,
+            }));
+        });
+    });
+
+    if super::is_cancelled() {
+        return Err(super::cancelled());
+    }
+
+    Ok(())
+}
+
+/// The current time, which only moves forwards while fibers sleep if the clock is simulated.
+pub fn now() -> Instant {
+    tls::runtime(|runtime| match &runtime.clock {
+        Some(clock) => clock.now(),
+        None => Instant::now(),
+    })
+}
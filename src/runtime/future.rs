@@ -0,0 +1,438 @@
+//! Bridge between fibers and [std::future::Future].
+//!
+//! Futures are polled from inside a fiber, which parks whenever the future is pending.
+//! Their I/O goes through the same io_uring as fibers, identified by a flag in the user data.
+
+use std::any::Any;
+use std::future::{Future, IntoFuture};
+use std::os::fd::RawFd;
+use std::pin::{pin, Pin};
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::{Arc, Mutex};
+use std::{io, marker, task, thread};
+
+use super::{syscall, tls, FiberIndex, JoinHandle, JoinHandleState, SendWaker, WaitingOn};
+
+/// Distinguishes operations issued by futures from syscalls issued by fibers.
+const OPERATION_FLAG: u64 = 1 << 63;
+
+/// Runs a future to completion on the running fiber, letting other fibers run while it's pending.
+///
+/// Fails immediately if the fiber is cancelled, dropping the future.
+pub fn block_on<F: Future>(future: F) -> crate::CancellableResult<F::Output> {
+    let mut future = pin!(future);
+
+    let state = Arc::new(WakeState {
+        fiber: tls::runtime(|runtime| runtime.running_fiber.unwrap()).0,
+        thread: thread::current().id(),
+        status: AtomicU8::new(POLLING),
+        remote: Mutex::new(None),
+    });
+    let _finish = Finish(state.clone());
+    let waker = task::Waker::from(state.clone());
+    let mut context = task::Context::from_waker(&waker);
+
+    loop {
+        if super::is_cancelled() {
+            return Err(super::cancelled());
+        }
+
+        state.status.store(POLLING, Ordering::Release);
+        if let task::Poll::Ready(output) = future.as_mut().poll(&mut context) {
+            return Ok(output);
+        }
+
+        // woken up while polling, so poll again right away
+        if state.status.load(Ordering::Acquire) != POLLING {
+            continue;
+        }
+
+        // woken up by waker or cancellation
+        super::park_on(WaitingOn::Future, |waker| {
+            *state.remote.lock().unwrap() = Some(waker.to_send());
+
+            let status = &state.status;
+            if status
+                .compare_exchange(POLLING, PARKED, Ordering::AcqRel, Ordering::Acquire)
+                .is_err()
+            {
+                waker.schedule(); // woken up in between
+            }
+        });
+        drop(state.remote.lock().unwrap().take()); // stale, next park gets a new one
+    }
+}
+
+const POLLING: u8 = 0;
+const PARKED: u8 = 1;
+const NOTIFIED: u8 = 2;
+const FINISHED: u8 = 3;
+
+/// Shared between a fiber blocking on a future and the future's wakers.
+#[derive(Debug)]
+struct WakeState {
+    fiber: usize,
+    thread: thread::ThreadId,
+    status: AtomicU8,
+    remote: Mutex<Option<SendWaker>>, // for wake ups from other threads
+}
+
+impl task::Wake for WakeState {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        let notified = self
+            .status
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |status| {
+                matches!(status, POLLING | PARKED).then_some(NOTIFIED)
+            });
+
+        // only the fiber's own park is interrupted, not whatever it does after block_on returns
+        if notified == Ok(PARKED) {
+            if thread::current().id() == self.thread {
+                super::Waker(FiberIndex(self.fiber)).schedule();
+            } else if let Some(waker) = self.remote.lock().unwrap().take() {
+                waker.wake();
+            }
+        }
+    }
+}
+
+/// Stops wakers from scheduling the fiber once [block_on] returns or unwinds.
+struct Finish(Arc<WakeState>);
+
+impl Drop for Finish {
+    fn drop(&mut self) {
+        self.0.status.store(FINISHED, Ordering::Release);
+    }
+}
+
+/// An io_uring operation issued by a future.
+#[derive(Debug)]
+pub(super) struct Operation {
+    result: Option<i32>,
+    waker: Option<task::Waker>,
+    is_orphaned: bool, // future was dropped before completion
+}
+
+/// Whether the completion belongs to a future rather than a fiber.
+pub(super) fn is_operation(user_data: syscall::Id) -> bool {
+    user_data.0 & OPERATION_FLAG != 0
+}
+
+fn operation_id(key: usize) -> syscall::Id {
+    syscall::Id(key as u64 | OPERATION_FLAG)
+}
+
+impl super::RuntimeState {
+    /// Records the result of an operation issued by a future.
+    /// Returns the waker of the future to wake up, unless the future was dropped.
+    pub(super) fn complete_future_operation(
+        &mut self,
+        user_data: syscall::Id,
+        result: i32,
+    ) -> Option<task::Waker> {
+        let key = (user_data.0 & !OPERATION_FLAG) as usize;
+        let operation = &mut self.future_operations[key];
+
+        if operation.is_orphaned {
+            self.future_operations.remove(key);
+            return None;
+        }
+
+        operation.result = Some(result);
+        operation.waker.take()
+    }
+}
+
+/// Waits for a file descriptor to become ready, like `poll(2)`.
+///
+/// Resolves to the events that occurred, see [Readiness].
+pub fn readiness(fd: RawFd, events: u32) -> Readiness {
+    Readiness {
+        fd,
+        events,
+        operation: None,
+        _not_send: marker::PhantomData,
+    }
+}
+
+/// Future returned by [readiness].
+///
+/// Uses io_uring's `PollAdd`, so it only makes progress on a Uringy runtime's thread.
+/// It can't be sent to another thread, since its operation belongs to this thread's runtime.
+///
+/// ```compile_fail
+/// fn assert_send(_: impl Send) {}
+/// assert_send(uringy::runtime::readiness(0, 1));
+/// ```
+#[derive(Debug)]
+#[must_use = "futures do nothing unless polled"]
+pub struct Readiness {
+    fd: RawFd,
+    events: u32,
+    operation: Option<usize>, // key into the runtime's future operations
+    _not_send: marker::PhantomData<*const ()>,
+}
+
+impl Future for Readiness {
+    type Output = io::Result<u32>;
+
+    fn poll(mut self: Pin<&mut Self>, context: &mut task::Context<'_>) -> task::Poll<Self::Output> {
+        let Some(key) = self.operation else {
+            let key = tls::runtime(|runtime| {
+                let key = runtime.future_operations.insert(Operation {
+                    result: None,
+                    waker: Some(context.waker().clone()),
+                    is_orphaned: false,
+                });
+
+                let fd = io_uring::types::Fd(self.fd);
+                let sqe = io_uring::opcode::PollAdd::new(fd, self.events).build();
+                runtime.kernel.issue(operation_id(key), sqe);
+
+                key
+            });
+            self.operation = Some(key);
+
+            return task::Poll::Pending;
+        };
+
+        let result = tls::runtime(|runtime| {
+            let operation = &mut runtime.future_operations[key];
+            if operation.result.is_none() {
+                operation.waker = Some(context.waker().clone());
+            }
+            operation.result
+        });
+
+        let Some(result) = result else {
+            return task::Poll::Pending;
+        };
+
+        tls::runtime(|runtime| runtime.future_operations.remove(key));
+        self.operation = None;
+
+        if result >= 0 {
+            task::Poll::Ready(Ok(result as u32))
+        } else {
+            task::Poll::Ready(Err(io::Error::from_raw_os_error(-result)))
+        }
+    }
+}
+
+impl Drop for Readiness {
+    fn drop(&mut self) {
+        let Some(key) = self.operation else {
+            return;
+        };
+
+        tls::runtime(|runtime| {
+            let operation = &mut runtime.future_operations[key];
+
+            if operation.result.is_some() {
+                runtime.future_operations.remove(key);
+            } else {
+                // cleaned up once the cancelled operation completes
+                operation.is_orphaned = true;
+                operation.waker = None;
+                runtime.kernel.cancel(operation_id(key));
+            }
+        });
+    }
+}
+
+impl<T> IntoFuture for JoinHandle<T> {
+    type Output = Result<T, crate::Error<Box<dyn Any + Send + 'static>>>;
+    type IntoFuture = JoinFuture<T>;
+
+    /// Awaits the fiber's output from async code running on the same thread, e.g. inside [block_on].
+    fn into_future(self) -> Self::IntoFuture {
+        JoinFuture(Some(self))
+    }
+}
+
+/// Future returned by [JoinHandle::into_future].
+#[derive(Debug)]
+#[must_use = "futures do nothing unless polled"]
+pub struct JoinFuture<T>(Option<JoinHandle<T>>);
+
+impl<T> Unpin for JoinFuture<T> {}
+
+impl<T> Future for JoinFuture<T> {
+    type Output = Result<T, crate::Error<Box<dyn Any + Send + 'static>>>;
+
+    fn poll(mut self: Pin<&mut Self>, context: &mut task::Context<'_>) -> task::Poll<Self::Output> {
+        let fiber = self.0.as_ref().expect("polled after completion").fiber;
+
+        let is_completed = tls::runtime(|runtime| {
+            let fiber = &mut runtime.fibers[fiber.0];
+            if !fiber.is_completed {
+                fiber.join_handle = JoinHandleState::Polled(context.waker().clone());
+            }
+            fiber.is_completed
+        });
+
+        if !is_completed {
+            return task::Poll::Pending;
+        }
+
+        let handle = self.0.take().unwrap();
+        task::Poll::Ready(handle.read_output())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::runtime::{cancel, spawn, start};
+
+    use super::*;
+
+    #[test]
+    fn returns_ready_output() {
+        start(|| {
+            let output = block_on(async { 123 });
+
+            assert_eq!(output, Ok(123));
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn polls_again_after_waking_itself() {
+        start(|| {
+            let mut polls = 0;
+            let future = std::future::poll_fn(|context| {
+                polls += 1;
+                if polls < 3 {
+                    context.waker().wake_by_ref();
+                    return task::Poll::Pending;
+                }
+                task::Poll::Ready(polls)
+            });
+
+            assert_eq!(block_on(future), Ok(3));
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn fails_when_cancelled() {
+        start(|| {
+            cancel();
+
+            let result = block_on(async {});
+
+            assert_eq!(
+                result,
+                Err(crate::Error::Cancelled(
+                    crate::CancellationReason::Requested
+                ))
+            );
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn waits_for_readiness() {
+        start(|| {
+            let mut fds = [0; 2];
+            assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+            let [r, w] = fds;
+
+            spawn(move || {
+                crate::time::sleep(Duration::from_millis(1)).unwrap();
+                assert_eq!(unsafe { libc::write(w, b"x".as_ptr().cast(), 1) }, 1);
+            });
+
+            let events = block_on(readiness(r, libc::POLLIN as u32))
+                .unwrap()
+                .unwrap();
+
+            assert_ne!(events & libc::POLLIN as u32, 0);
+            unsafe { libc::close(r) };
+            unsafe { libc::close(w) };
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn cancels_dropped_readiness() {
+        start(|| {
+            let mut fds = [0; 2];
+            assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+
+            let mut future = Box::pin(readiness(fds[0], libc::POLLIN as u32));
+            let waker = task::Waker::from(Arc::new(Noop));
+            let mut context = task::Context::from_waker(&waker);
+            assert!(future.as_mut().poll(&mut context).is_pending());
+            drop(future);
+
+            crate::time::sleep(Duration::from_millis(1)).unwrap();
+            unsafe { libc::close(fds[0]) };
+            unsafe { libc::close(fds[1]) };
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn woken_from_other_thread() {
+        start(|| {
+            let mut is_spawned = false;
+            let future = std::future::poll_fn(|context| {
+                if is_spawned {
+                    return task::Poll::Ready(123);
+                }
+                is_spawned = true;
+
+                let waker = context.waker().clone();
+                thread::spawn(move || {
+                    thread::sleep(Duration::from_millis(1));
+                    waker.wake();
+                });
+                task::Poll::Pending
+            });
+
+            assert_eq!(block_on(future), Ok(123));
+        })
+        .unwrap();
+    }
+
+    struct Noop;
+
+    impl task::Wake for Noop {
+        fn wake(self: Arc<Self>) {}
+    }
+
+    #[test]
+    fn awaits_fiber() {
+        start(|| {
+            let handle = spawn(|| {
+                crate::time::sleep(Duration::from_millis(1)).unwrap();
+                123
+            });
+
+            let output = block_on(async { handle.await.unwrap() });
+
+            assert_eq!(output, Ok(123));
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn awaits_completed_fiber() {
+        start(|| {
+            let handle = spawn(|| 123);
+            crate::runtime::yield_now();
+
+            let output = block_on(handle.into_future());
+
+            assert_eq!(output.unwrap().unwrap(), 123);
+        })
+        .unwrap();
+    }
+}
@@ -0,0 +1,158 @@
+//! Wake ups from threads other than the runtime's.
+//!
+//! Fibers are pushed onto a lock-free stack, then an eventfd is written to interrupt the runtime if it's waiting for I/O.
+
+use std::os::fd::RawFd;
+use std::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::{io, ptr};
+
+use super::FiberIndex;
+
+/// Identifies the completion of reading from the eventfd.
+pub(super) const USER_DATA: u64 = u64::MAX - 1;
+
+#[derive(Debug)]
+pub(super) struct Inbox {
+    head: AtomicPtr<Node>,
+    eventfd: RawFd,
+    live_wakers: AtomicUsize,
+}
+
+#[derive(Debug)]
+struct Node {
+    fiber: FiberIndex,
+    park_token: u64,
+    next: *mut Node,
+}
+
+impl Inbox {
+    pub(super) fn new() -> io::Result<Self> {
+        let eventfd = unsafe { libc::eventfd(0, libc::EFD_CLOEXEC | libc::EFD_NONBLOCK) };
+        if eventfd == -1 {
+            let error = io::Error::last_os_error();
+            return Err(error);
+        }
+
+        Ok(Inbox {
+            head: AtomicPtr::new(ptr::null_mut()),
+            eventfd,
+            live_wakers: AtomicUsize::new(0),
+        })
+    }
+
+    /// File descriptor that becomes readable after a wake up.
+    pub(super) fn eventfd(&self) -> RawFd {
+        self.eventfd
+    }
+
+    /// Whether any [SendWaker] could still wake up a fiber.
+    pub(super) fn has_live_wakers(&self) -> bool {
+        self.live_wakers.load(Ordering::Acquire) > 0
+    }
+
+    /// Takes every woken fiber along with the park it was woken from, oldest first.
+    pub(super) fn drain(&self) -> Vec<(FiberIndex, u64)> {
+        let mut woken = vec![];
+
+        // fast path
+        if self.head.load(Ordering::Relaxed).is_null() {
+            return woken;
+        }
+
+        let mut node = self.head.swap(ptr::null_mut(), Ordering::Acquire);
+        while !node.is_null() {
+            let boxed = unsafe { Box::from_raw(node) }; // safety: nodes are only taken once by swapping the head
+            woken.push((boxed.fiber, boxed.park_token));
+            node = boxed.next;
+        }
+
+        woken.reverse();
+        woken
+    }
+
+    fn push(&self, fiber: FiberIndex, park_token: u64) {
+        let node = Box::into_raw(Box::new(Node {
+            fiber,
+            park_token,
+            next: ptr::null_mut(),
+        }));
+
+        let mut head = self.head.load(Ordering::Relaxed);
+        loop {
+            unsafe { (*node).next = head };
+            match self
+                .head
+                .compare_exchange_weak(head, node, Ordering::Release, Ordering::Relaxed)
+            {
+                Ok(_) => break,
+                Err(actual) => head = actual,
+            }
+        }
+    }
+
+    /// Interrupts the runtime if it's waiting for I/O.
+    fn kick(&self) {
+        let value: u64 = 1;
+        let result = unsafe { libc::write(self.eventfd, ptr::addr_of!(value).cast(), 8) };
+        // EAGAIN means the counter is saturated, so the runtime will be interrupted anyways
+        debug_assert!(
+            result == 8 || io::Error::last_os_error().raw_os_error() == Some(libc::EAGAIN)
+        );
+    }
+}
+
+impl Drop for Inbox {
+    fn drop(&mut self) {
+        drop(self.drain());
+        unsafe { libc::close(self.eventfd) };
+    }
+}
+
+/// Handle for scheduling a parked fiber from any thread.
+///
+/// Obtained with [super::Waker::to_send].
+/// Only wakes the fiber from the park it was obtained in, later wake ups are ignored.
+#[derive(Debug)]
+pub struct SendWaker {
+    inbox: Arc<Inbox>,
+    fiber: FiberIndex,
+    park_token: u64,
+}
+
+impl SendWaker {
+    pub(super) fn new(inbox: Arc<Inbox>, fiber: FiberIndex, park_token: u64) -> Self {
+        inbox.live_wakers.fetch_add(1, Ordering::AcqRel);
+        SendWaker {
+            inbox,
+            fiber,
+            park_token,
+        }
+    }
+
+    /// Wake up the parked fiber to be run at some point.
+    pub fn wake(self) {
+        self.wake_by_ref();
+    }
+
+    /// Wake up the parked fiber to be run at some point, without consuming the waker.
+    pub fn wake_by_ref(&self) {
+        self.inbox.push(self.fiber, self.park_token);
+        self.inbox.kick();
+    }
+}
+
+impl Clone for SendWaker {
+    fn clone(&self) -> Self {
+        SendWaker::new(self.inbox.clone(), self.fiber, self.park_token)
+    }
+}
+
+impl Drop for SendWaker {
+    fn drop(&mut self) {
+        // runtime might be waiting on this waker, it needs to check for deadlock again
+        if self.inbox.live_wakers.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.inbox.kick();
+        }
+    }
+}
@@ -3,60 +3,422 @@
 use std::any::Any;
 use std::collections::{BTreeSet, VecDeque};
 use std::num::NonZeroUsize;
-use std::{ffi, hint, io, marker, mem, panic, thread};
-
+use std::os::fd::RawFd;
+use std::sync::Arc;
+use std::time::Duration;
+use std::{ffi, fmt, hint, io, marker, mem, panic, process, task, thread};
+
+pub(crate) use blocking::unblock;
+pub(crate) use buffers::{register_buffer, unregister_buffer, Registration};
+pub(crate) use clock::{is_simulated as is_clock_simulated, now, sleep as sleep_simulated};
+pub use future::{block_on, readiness, JoinFuture, Readiness};
+pub use inbox::SendWaker;
+pub use poll::{poll, poll_multishot, wait_readable, wait_writable, Poller};
+
+mod blocking;
+mod buffers;
+mod clock;
 mod context_switch;
+mod future;
+mod inbox;
+mod poll;
 mod stack;
 mod syscall;
 mod tls;
+mod watchdog;
 
 /// ...
 pub fn start<F: FnOnce() -> T, T>(f: F) -> thread::Result<T> {
-    tls::exclusive_runtime(|| {
-        let (original, root) = tls::runtime(|runtime| {
-            let root_fiber = runtime.create_fiber(f, start_trampoline::<F, T>, false);
-            runtime.running_fiber = Some(root_fiber);
-
-            (
-                runtime.original.as_mut_ptr(),
-                &runtime.running().continuation as *const context_switch::Continuation,
-            )
+    Builder::new().start(f)
+}
+
+/// Runtime configuration, mirroring [std::thread::Builder].
+#[derive(Debug, Clone)]
+pub struct Builder {
+    ring_entries: u32,
+    stack_size: usize,
+    stack_pool_capacity: usize,
+    dirty_stack_limit: usize,
+    track_stack_usage: bool,
+    on_deadlock: Option<fn(&Deadlock)>,
+    syscall_budget: u32,
+    watchdog: Option<Duration>,
+    on_watchdog_report: Option<fn(&str)>,
+    simulated_clock: bool,
+}
+
+impl Builder {
+    /// Creates the default configuration.
+    pub fn new() -> Self {
+        Builder {
+            ring_entries: 1024,
+            stack_size: 128 * 1024,
+            stack_pool_capacity: 1024,
+            dirty_stack_limit: 64,
+            track_stack_usage: false,
+            on_deadlock: None,
+            syscall_budget: 64,
+            watchdog: None,
+            on_watchdog_report: None,
+            simulated_clock: false,
+        }
+    }
+
+    /// Sets the size of the io_uring submission queue, rounded up to a power of two.
+    ///
+    /// Clamped to the kernel's maximum rather than failing.
+    pub fn ring_entries(mut self, entries: u32) -> Self {
+        self.ring_entries = entries;
+        self
+    }
+
+    /// Sets the usable size of each fiber's stack in bytes, rounded up to whole pages.
+    ///
+    /// Stacks are demand paged, so only the pages that are actually touched take up physical memory.
+    pub fn stack_size(mut self, size: usize) -> Self {
+        self.stack_size = size;
+        self
+    }
+
+    /// Sets the maximum number of stacks kept around for reuse after their fibers complete.
+    pub fn stack_pool_capacity(mut self, capacity: usize) -> Self {
+        self.stack_pool_capacity = capacity;
+        self
+    }
+
+    /// Sets the maximum number of pooled stacks that keep their pages.
+    ///
+    /// The pages of stacks past this limit are given back to the kernel with `MADV_FREE`.
+    pub fn dirty_stack_limit(mut self, limit: usize) -> Self {
+        self.dirty_stack_limit = limit;
+        self
+    }
+
+    /// Sets whether to measure how deep each fiber's stack grows, see [stack_usage].
+    ///
+    /// Stacks are painted with a canary pattern, which faults in all of their pages.
+    /// Intended for sizing stacks during development.
+    pub fn track_stack_usage(mut self, track: bool) -> Self {
+        self.track_stack_usage = track;
+        self
+    }
+
+    /// Sets the function that's called when every fiber is parked and no I/O is in flight.
+    ///
+    /// By default, the runtime panics with a report of the parked fibers.
+    /// If the hook returns, [Builder::start] returns the [Deadlock] as an error without running the remaining fibers.
+    pub fn on_deadlock(mut self, hook: fn(&Deadlock)) -> Self {
+        self.on_deadlock = Some(hook);
+        self
+    }
+
+    /// Sets how many syscalls in a row a fiber can complete without giving other fibers a chance to run.
+    ///
+    /// Syscalls that complete as soon as they're submitted, like reading from the page cache, don't park the fiber.
+    /// Once the budget runs out, the fiber yields.
+    /// A budget of 0 parks on every syscall.
+    pub fn syscall_budget(mut self, budget: u32) -> Self {
+        self.syscall_budget = budget;
+        self
+    }
+
+    /// Spawns a thread that logs fibers that run for longer than the threshold without yielding.
+    ///
+    /// The fiber's id and name are logged right away, its backtrace is logged once it finally yields.
+    pub fn watchdog(mut self, threshold: Duration) -> Self {
+        self.watchdog = Some(threshold);
+        self
+    }
+
+    /// Sets the hook that's called with the watchdog's reports, see [Builder::watchdog].
+    ///
+    /// By default, reports are written to stderr.
+    /// The hook is called from both the watchdog's thread and the runtime's thread.
+    pub fn on_watchdog_report(mut self, hook: fn(&str)) -> Self {
+        self.on_watchdog_report = Some(hook);
+        self
+    }
+
+    /// Sets whether time is simulated, for deterministic tests.
+    ///
+    /// Instead of waiting, [crate::time::sleep] jumps the clock forwards once every fiber is parked and no I/O is in flight.
+    /// [crate::time::now] reports the simulated time.
+    pub fn simulated_clock(mut self, simulated: bool) -> Self {
+        self.simulated_clock = simulated;
+        self
+    }
+
+    /// Runs the closure in a new runtime on the current thread.
+    pub fn start<F: FnOnce() -> T, T>(self, f: F) -> thread::Result<T> {
+        let output = tls::exclusive_runtime(RuntimeState::new(&self), || {
+            let (original, root) = tls::runtime(|runtime| {
+                let root_fiber = runtime.create_fiber(f, start_trampoline::<F, T>, false, None);
+                runtime.running_fiber = Some(root_fiber);
+
+                (
+                    runtime.original.as_mut_ptr(),
+                    &runtime.running().continuation as *const context_switch::Continuation,
+                )
+            });
+
+            unsafe { context_switch::jump(original, root) };
+            tls::runtime(|runtime| {
+                runtime.recycle_dead_stack();
+
+                if let Some(deadlock) = runtime.deadlock.take() {
+                    return Err(deadlock);
+                }
+
+                Ok(unsafe {
+                    let stack_base = runtime.running().stack_base();
+                    stack_base.union_ref::<thread::Result<T>>().read()
+                })
+            })
         });
 
-        unsafe { context_switch::jump(original, root) };
-        tls::runtime(|rt| unsafe { rt.running().stack.union_ref::<thread::Result<T>>().read() })
-    })
+        output.unwrap_or_else(|deadlock| {
+            match self.on_deadlock {
+                Some(hook) => hook(&deadlock),
+                None => panic!("{deadlock}"),
+            }
+
+            Err(Box::new(deadlock))
+        })
+    }
+
+    /// Runs the closure in a new runtime on each CPU core, with every thread pinned to its core.
+    ///
+    /// Returns each runtime's result once they all complete.
+    pub fn start_per_core<F, T>(self, f: F) -> Vec<thread::Result<T>>
+    where
+        F: Fn() -> T + Send + Sync,
+        T: Send,
+    {
+        let cores = thread::available_parallelism().map_or(1, NonZeroUsize::get);
+
+        thread::scope(|scope| {
+            let threads: Vec<_> = (0..cores)
+                .map(|core| {
+                    let builder = self.clone();
+                    let f = &f;
+                    thread::Builder::new()
+                        .name(format!("uringy-{core}"))
+                        .spawn_scoped(scope, move || {
+                            pin_to_core(core);
+                            builder.start(f)
+                        })
+                        .unwrap()
+                })
+                .collect();
+
+            threads
+                .into_iter()
+                .map(|thread| thread.join().and_then(|output| output))
+                .collect()
+        })
+    }
+
+    fn usable_stack_pages(&self) -> NonZeroUsize {
+        let pages = self.stack_size.div_ceil(stack::page_size());
+        NonZeroUsize::new(pages).unwrap_or(NonZeroUsize::MIN)
+    }
+}
+
+/// Pins the thread to the n-th core it's allowed to run on, which isn't core n under `taskset` or cgroups.
+///
+/// Best effort, the thread keeps running on any allowed core if pinning fails.
+fn pin_to_core(n: usize) {
+    unsafe {
+        let size = mem::size_of::<libc::cpu_set_t>();
+        let mut allowed: libc::cpu_set_t = mem::zeroed();
+        if libc::sched_getaffinity(0, size, &mut allowed) != 0 {
+            return;
+        }
+
+        let mut cores =
+            (0..libc::CPU_SETSIZE as usize).filter(|&core| libc::CPU_ISSET(core, &allowed));
+        let Some(core) = cores.nth(n) else {
+            return;
+        };
+
+        let mut set: libc::cpu_set_t = mem::zeroed();
+        libc::CPU_SET(core, &mut set);
+        libc::sched_setaffinity(0, size, &set);
+    }
+}
+
+/// Runs the program's main function, used by `#[uringy::start]`.
+///
+/// Panics have already been reported by the panic hook, so they only set the exit code.
+#[doc(hidden)]
+pub fn run_main<F: FnOnce() -> T, T: process::Termination>(
+    builder: Builder,
+    f: F,
+) -> process::ExitCode {
+    match builder.start(|| f().report()) {
+        Ok(code) => code,
+        Err(_) => process::ExitCode::from(101), // same as an unwinding main thread
+    }
+}
+
+/// Runs the program's main function on every core, used by `#[uringy::start(per_core = true)]`.
+///
+/// Errors are printed like [process::Termination] does for `Result`, failing if any runtime fails.
+#[doc(hidden)]
+pub fn run_main_per_core<F, E>(builder: Builder, f: F) -> process::ExitCode
+where
+    F: Fn() -> Result<(), E> + Send + Sync,
+    E: fmt::Debug,
+{
+    let results = builder.start_per_core(|| f().map_err(|error| eprintln!("Error: {error:?}")));
+
+    if results.iter().any(Result::is_err) {
+        process::ExitCode::from(101)
+    } else if results.iter().any(|result| matches!(result, Ok(Err(())))) {
+        process::ExitCode::FAILURE
+    } else {
+        process::ExitCode::SUCCESS
+    }
+}
+
+/// Runs a test in a new runtime, used by `#[uringy::test]`.
+///
+/// Panics with the test's name if the test panics, or if it doesn't complete before the timeout.
+/// The test is cancelled with [crate::CancellationReason::Timeout] when it times out.
+#[doc(hidden)]
+pub fn run_test<F: FnOnce() -> T + 'static, T: 'static>(
+    name: &'static str,
+    builder: Builder,
+    timeout: Option<Duration>,
+    f: F,
+) -> T {
+    /// Whether the test finished or timed out, shared with the timeout thread.
+    #[derive(Default)]
+    struct Outcome {
+        is_finished: Option<bool>,
+        waker: Option<Waker>,
+        remote: Option<SendWaker>, // only while a timeout thread exists, so deadlocks are still detected
+    }
+
+    /// Lets the runner know the test is done, even if it panics.
+    struct Finish(Arc<std::sync::Mutex<Outcome>>);
+
+    impl Drop for Finish {
+        fn drop(&mut self) {
+            let mut outcome = self.0.lock().unwrap();
+            outcome.is_finished.get_or_insert(true);
+            outcome.remote = None;
+            if let Some(waker) = outcome.waker.take() {
+                drop(outcome);
+                waker.schedule();
+            }
+        }
+    }
+
+    let result = builder.start(move || {
+        let outcome = Arc::new(std::sync::Mutex::new(Outcome::default()));
+
+        let test = spawn({
+            let finish = Finish(outcome.clone());
+            move || {
+                let output = f();
+                drop(finish);
+                output
+            }
+        });
+
+        // real time on another thread, even if the clock is simulated
+        let (done, is_done) = std::sync::mpsc::channel::<()>();
+        let timer = timeout.map(|timeout| {
+            let outcome = outcome.clone();
+            thread::spawn(move || {
+                if is_done.recv_timeout(timeout).is_err() {
+                    let mut outcome = outcome.lock().unwrap();
+                    outcome.is_finished.get_or_insert(false);
+                    outcome.waker = None;
+                    if let Some(waker) = outcome.remote.take() {
+                        drop(outcome);
+                        waker.wake();
+                    }
+                }
+            })
+        });
+
+        let is_finished = loop {
+            if let Some(is_finished) = outcome.lock().unwrap().is_finished {
+                break is_finished;
+            }
+
+            park_on(WaitingOn::Join(test.fiber.0), |waker| {
+                let mut outcome = outcome.lock().unwrap();
+                if timer.is_some() {
+                    outcome.remote = Some(waker.to_send());
+                }
+                outcome.waker = Some(waker);
+            });
+        };
+
+        let _ = done.send(());
+        if let Some(timer) = timer {
+            timer.join().unwrap();
+        }
+
+        if !is_finished {
+            test.cancel_with(crate::CancellationReason::Timeout);
+            let _ = test.join();
+            panic!("timed out after {:?}", timeout.unwrap());
+        }
+
+        match test.join() {
+            Ok(output) => output,
+            Err(crate::Error::Original(payload)) => panic::resume_unwind(payload),
+            Err(crate::Error::Cancelled(reason)) => panic!("{reason}"),
+        }
+    });
+
+    match result {
+        Ok(output) => output,
+        Err(payload) => {
+            let message = payload
+                .downcast_ref::<&str>()
+                .copied()
+                .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+                .unwrap_or("Box<dyn Any>");
+            panic!("test {name} failed: {message}");
+        }
+    }
+}
+
+impl Default for Builder {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 extern "C" fn start_trampoline<F: FnOnce() -> T, T>() -> ! {
     // execute closure
     let closure: F = tls::runtime(|runtime| {
         let fiber = runtime.running();
-        unsafe { fiber.stack.union_ref::<F>().read() }
+        unsafe { fiber.stack_base().union_ref::<F>().read() }
     });
 
-    let result = panic::catch_unwind(panic::AssertUnwindSafe(|| (closure)()));
+    let result = panic::catch_unwind(panic::AssertUnwindSafe(closure));
     hint::black_box(&result); // removing this causes a segfault in release mode
 
     tls::runtime(|runtime| {
         let fiber = runtime.running();
         fiber.is_completed = true;
         fiber.is_cancelled = true; // prevent cancel scheduling while waiting for children
-        unsafe { fiber.stack.union_mut::<thread::Result<T>>().write(result) };
+        let mut stack_base = fiber.stack_base();
+        unsafe { stack_base.union_mut::<thread::Result<T>>().write(result) };
     });
 
     // wait for children
     if tls::runtime(|rt| !rt.running().children.is_empty()) {
-        park(|_| {}); // woken up by last child
+        park_on(WaitingOn::Children, |_| {}); // woken up by last child
     }
 
-    // deallocate stack
-    tls::runtime(|runtime| {
-        let stack = runtime.running().stack;
-        runtime.stack_pool.push(stack);
-    });
-
-    // return to original thread
+    // return to original thread, which reads the output from this stack
     let mut dummy = mem::MaybeUninit::uninit();
     let original = tls::runtime(|runtime| runtime.original.as_ptr());
     unsafe { context_switch::jump(dummy.as_mut_ptr(), original) };
@@ -65,21 +427,61 @@ extern "C" fn start_trampoline<F: FnOnce() -> T, T>() -> ! {
 
 struct RuntimeState {
     kernel: syscall::Interface,
+    helper: Option<blocking::Helper>, // spawned on first use, joined before the stacks its jobs borrow from are freed
     fibers: slab::Slab<FiberState>,
     ready_fibers: VecDeque<FiberIndex>,
     running_fiber: Option<FiberIndex>,
-    stack_pool: Vec<StackBase>,
+    stack_pool: stack::Pool,
+    dead_stack: Option<stack::Stack>, // can't recycle a stack while it's still in use
+    deadlock: Option<Deadlock>,
+    future_operations: slab::Slab<future::Operation>,
+    multishots: slab::Slab<poll::Multishot>,
+    inbox: Arc<inbox::Inbox>,
+    inbox_buffer: Box<u64>, // written to by the kernel while reading the eventfd
+    is_inbox_armed: bool,
+    park_tokens: u64,
+    syscall_budget: u32,
+    syscall_streak: u32, // syscalls completed by the running fiber without parking
+    watchdog: Option<watchdog::Watchdog>,
+    buffers: buffers::Table,
+    clock: Option<clock::Clock>,
+    #[cfg(test)]
+    unsupported_opcodes: Vec<u8>,
     original: mem::MaybeUninit<context_switch::Continuation>,
 }
 
 impl RuntimeState {
-    fn new() -> Self {
+    fn new(builder: &Builder) -> Self {
         RuntimeState {
-            kernel: syscall::Interface::new(),
+            kernel: syscall::Interface::new(builder.ring_entries),
+            helper: None,
             fibers: slab::Slab::new(),
             ready_fibers: VecDeque::new(),
             running_fiber: None,
-            stack_pool: Vec::new(),
+            stack_pool: stack::Pool::new(
+                builder.usable_stack_pages(),
+                builder.stack_pool_capacity,
+                builder.dirty_stack_limit,
+                builder.track_stack_usage,
+            ),
+            dead_stack: None,
+            deadlock: None,
+            future_operations: slab::Slab::new(),
+            multishots: slab::Slab::new(),
+            inbox: Arc::new(inbox::Inbox::new().unwrap()),
+            inbox_buffer: Box::new(0),
+            is_inbox_armed: false,
+            park_tokens: 0,
+            syscall_budget: builder.syscall_budget,
+            syscall_streak: 0,
+            watchdog: builder.watchdog.map(|threshold| {
+                let report = builder.on_watchdog_report.unwrap_or(watchdog::log);
+                watchdog::Watchdog::new(threshold, report).unwrap()
+            }),
+            buffers: buffers::Table::new(),
+            clock: builder.simulated_clock.then(clock::Clock::new),
+            #[cfg(test)]
+            unsupported_opcodes: Vec::new(),
             original: mem::MaybeUninit::uninit(),
         }
     }
@@ -89,20 +491,16 @@ impl RuntimeState {
         f: F,
         trampoline: extern "C" fn() -> !,
         is_cancelled: bool,
+        cancellation_reason: Option<crate::CancellationReason>,
     ) -> FiberIndex {
         // allocate stack
-        let mut stack_base = self.stack_pool.pop().unwrap_or_else(|| {
-            let usable_pages = NonZeroUsize::new(32).unwrap();
-            let stack = stack::Stack::new(NonZeroUsize::MIN, usable_pages).unwrap();
-            let stack_base = StackBase(stack.base());
-            mem::forget(stack);
-            stack_base
-        });
+        let stack = self.stack_pool.acquire().unwrap();
+        let mut stack_base = StackBase(stack.base());
 
         unsafe { stack_base.union_mut::<F>().write(f) };
 
         let index = self.fibers.insert(FiberState {
-            stack: stack_base,
+            stack,
             continuation: unsafe {
                 context_switch::prepare_stack(stack_base.after_union::<F, T>(), trampoline)
             },
@@ -110,8 +508,13 @@ impl RuntimeState {
             parent: None,
             children: BTreeSet::new(),
             syscall_result: None,
+            waiting_on: None,
+            park_token: 0,
+            name: None,
             is_completed: false,
             is_cancelled,
+            cancellation_reason,
+            is_shielded: false,
             // is_scheduled: false,
         });
 
@@ -124,35 +527,141 @@ impl RuntimeState {
         &mut self.fibers[fiber_index.0]
     }
 
-    fn process_io(&mut self) -> *const context_switch::Continuation {
+    /// Picks the next fiber to run, waiting for I/O if none are ready.
+    /// Returns [None] when futures need to be woken up, which can't be done while the runtime is borrowed.
+    fn process_io(
+        &mut self,
+        wakers: &mut Vec<task::Waker>,
+    ) -> Option<*const context_switch::Continuation> {
         loop {
-            for (user_data, result) in self.kernel.process_completed() {
-                let fiber = FiberIndex(user_data.0 as usize);
-                self.fibers[fiber.0].syscall_result = Some(result);
-                Waker(fiber).schedule_with(self);
+            self.process_completed(wakers);
+
+            for (fiber, park_token) in self.inbox.drain() {
+                let Some(state) = self.fibers.get(fiber.0) else {
+                    continue; // completed since
+                };
+
+                if state.waiting_on.is_some() && state.park_token == park_token {
+                    Waker(fiber).schedule_with(self);
+                }
+            }
+
+            if !wakers.is_empty() {
+                break None;
             }
 
             if let Some(fiber) = self.ready_fibers.pop_front() {
                 self.running_fiber = Some(fiber);
+                self.syscall_streak = 0;
+                if let Some(watchdog) = &self.watchdog {
+                    watchdog.switched(fiber.0, self.fibers[fiber.0].name.clone());
+                }
                 // self.fibers[fiber.0].is_scheduled = false;
-                break &self.fibers[fiber.0].continuation as *const context_switch::Continuation;
+                break Some(
+                    &self.fibers[fiber.0].continuation as *const context_switch::Continuation,
+                );
             }
 
+            // simulated time only passes once nothing else can make progress
+            // waiting on other threads doesn't count, they run in real time
+            let has_timers = self.clock.as_ref().is_some_and(clock::Clock::has_timers);
+            let in_flight = self.kernel.in_flight() - usize::from(self.is_inbox_armed);
+            if has_timers && in_flight == 0 {
+                self.advance_clock();
+                continue;
+            }
+
+            // nothing could ever wake up a parked fiber, so give up on all of them
+            if self.kernel.in_flight() == 0 && !self.inbox.has_live_wakers() {
+                self.deadlock = Some(self.deadlock_report());
+                break Some(self.original.as_ptr());
+            }
+
+            // other threads interrupt waiting by writing to the eventfd
+            if self.inbox.has_live_wakers() && !self.is_inbox_armed {
+                let fd = io_uring::types::Fd(self.inbox.eventfd());
+                let buffer = &mut *self.inbox_buffer as *mut u64 as *mut u8;
+                let sqe = io_uring::opcode::Read::new(fd, buffer, 8).build();
+                self.kernel.issue(syscall::Id(inbox::USER_DATA), sqe);
+                self.is_inbox_armed = true;
+            }
+
+            self.stack_pool.trim();
+            if let Some(watchdog) = &self.watchdog {
+                watchdog.idle(true);
+            }
             self.kernel.wait_for_completed();
+            if let Some(watchdog) = &self.watchdog {
+                watchdog.idle(false);
+            }
+        }
+    }
+
+    /// Hands out the results of completed syscalls.
+    /// The running fiber isn't scheduled for its own syscall unless it's parked.
+    fn process_completed(&mut self, wakers: &mut Vec<task::Waker>) {
+        for (user_data, result, more) in self.kernel.process_completed() {
+            if user_data.0 == inbox::USER_DATA {
+                self.is_inbox_armed = false;
+                continue;
+            }
+
+            if future::is_operation(user_data) {
+                wakers.extend(self.complete_future_operation(user_data, result));
+                continue;
+            }
+
+            if poll::is_multishot(user_data) {
+                self.complete_multishot(user_data, result, more);
+                continue;
+            }
+
+            let fiber = FiberIndex(user_data.0 as usize);
+            let state = &mut self.fibers[fiber.0];
+            state.syscall_result = Some(result);
+
+            let is_running = self.running_fiber == Some(fiber) && state.waiting_on.is_none();
+            if !is_running {
+                Waker(fiber).schedule_with(self);
+            }
+        }
+    }
+
+    fn deadlock_report(&self) -> Deadlock {
+        let parked = self.fibers.iter().filter_map(|(index, fiber)| {
+            fiber.waiting_on.map(|waiting_on| ParkedFiber {
+                id: index,
+                parent: fiber.parent.map(|parent| parent.0),
+                waiting_on,
+            })
+        });
+
+        Deadlock {
+            parked: parked.collect(),
+        }
+    }
+
+    /// Returns the stack of the previously running fiber to the pool, now that it's been switched away from.
+    fn recycle_dead_stack(&mut self) {
+        if let Some(stack) = self.dead_stack.take() {
+            self.stack_pool.release(stack);
         }
     }
 
-    fn cancel(&mut self, root: FiberIndex) {
+    fn cancel(&mut self, root: FiberIndex, reason: crate::CancellationReason) {
         // TODO: if is_cancelled { return } (short circuit)
 
-        if !self.fibers[root.0].is_cancelled && root != self.running_fiber.unwrap() {
+        let fiber = &self.fibers[root.0];
+        if !fiber.is_cancelled && !fiber.is_shielded && root != self.running_fiber.unwrap() {
             Waker(root).schedule_with(self);
         }
 
-        self.fibers[root.0].is_cancelled = true;
+        let fiber = &mut self.fibers[root.0];
+        fiber.is_cancelled = true;
+        let reason = *fiber.cancellation_reason.get_or_insert(reason);
 
         for child in self.fibers[root.0].children.clone() {
-            self.cancel(child);
+            self.cancel(child, reason);
         }
     }
 
@@ -162,21 +671,6 @@ impl RuntimeState {
     }
 }
 
-impl Drop for RuntimeState {
-    fn drop(&mut self) {
-        let guard_pages = 1;
-        let usable_pages = 32;
-
-        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize };
-        let length = (guard_pages + usable_pages) * page_size;
-
-        for stack_bottom in self.stack_pool.drain(..) {
-            let pointer = unsafe { stack_bottom.0.byte_sub(length) };
-            drop(stack::Stack { pointer, length })
-        }
-    }
-}
-
 /// ...
 /// max 4.3 billion... (u32 takes up less space in FiberState)
 #[repr(transparent)]
@@ -185,20 +679,32 @@ struct FiberIndex(usize);
 
 #[derive(Debug)]
 struct FiberState {
-    stack: StackBase,
+    stack: stack::Stack,
     continuation: context_switch::Continuation,
     join_handle: JoinHandleState,
     parent: Option<FiberIndex>,
     children: BTreeSet<FiberIndex>,
     syscall_result: Option<i32>,
+    waiting_on: Option<WaitingOn>,
+    park_token: u64, // distinguishes parks, so stale wake ups from other threads are ignored
+    name: Option<Arc<str>>,
     is_completed: bool,
     is_cancelled: bool,
+    cancellation_reason: Option<crate::CancellationReason>,
+    is_shielded: bool,
+}
+
+impl FiberState {
+    fn stack_base(&self) -> StackBase {
+        StackBase(self.stack.base())
+    }
 }
 
 #[derive(Debug)]
 enum JoinHandleState {
     Unused,
     Waiting(Option<Waker>), // option for taking ownership from mutable reference
+    Polled(task::Waker),
     Dropped,
 }
 
@@ -217,7 +723,7 @@ impl StackBase {
     }
 
     unsafe fn after_union<F, T>(&self) -> *mut ffi::c_void {
-        let union_size = std::cmp::max(mem::size_of::<F>(), mem::size_of::<T>());
+        let union_size = std::cmp::max(mem::size_of::<F>(), mem::size_of::<thread::Result<T>>());
         self.0.byte_sub(union_size)
     }
 }
@@ -225,8 +731,10 @@ impl StackBase {
 /// Spawns a new fiber, returning a [JoinHandle] for it.
 pub fn spawn<F: FnOnce() -> T + 'static, T: 'static>(f: F) -> JoinHandle<T> {
     let child_fiber = tls::runtime(|runtime| {
-        let is_cancelled = runtime.running().is_cancelled;
-        let child_fiber = runtime.create_fiber(f, spawn_trampoline::<F, T>, is_cancelled);
+        let parent = runtime.running();
+        let is_cancelled = parent.is_cancelled && !parent.is_shielded;
+        let reason = parent.cancellation_reason.filter(|_| is_cancelled);
+        let child_fiber = runtime.create_fiber(f, spawn_trampoline::<F, T>, is_cancelled, reason);
         runtime.ready_fibers.push_back(child_fiber);
         // runtime.fibers[child_fiber.0].is_scheduled = true;
 
@@ -242,8 +750,11 @@ pub fn spawn<F: FnOnce() -> T + 'static, T: 'static>(f: F) -> JoinHandle<T> {
 
 extern "C" fn spawn_trampoline<F: FnOnce() -> T, T>() -> ! {
     // execute closure
-    let closure: F = tls::runtime(|rt| unsafe { rt.running().stack.union_ref::<F>().read() });
-    let result = panic::catch_unwind(panic::AssertUnwindSafe(|| (closure)()));
+    let closure: F = tls::runtime(|runtime| {
+        runtime.recycle_dead_stack();
+        unsafe { runtime.running().stack_base().union_ref::<F>().read() }
+    });
+    let result = panic::catch_unwind(panic::AssertUnwindSafe(closure));
     hint::black_box(&result); // removing this causes a segfault in release mode
     let result_is_error = result.is_err();
 
@@ -252,24 +763,34 @@ extern "C" fn spawn_trampoline<F: FnOnce() -> T, T>() -> ! {
 
         fiber.is_completed = true;
         fiber.is_cancelled = true; // prevent cancel scheduling while waiting for children
-        unsafe { fiber.stack.union_mut::<thread::Result<T>>().write(result) };
+        let mut stack_base = fiber.stack_base();
+        unsafe { stack_base.union_mut::<thread::Result<T>>().write(result) };
     });
 
     // wait for children
     if tls::runtime(|rt| !rt.running().children.is_empty()) {
-        park(|_| {}); // woken up by last child
+        park_on(WaitingOn::Children, |_| {}); // woken up by last child
     }
 
     // schedule joining fiber
-    tls::runtime(|runtime| {
+    let polled = tls::runtime(|runtime| {
         if let JoinHandleState::Waiting(waker) = &mut runtime.running().join_handle {
             waker.take().unwrap().schedule_with(runtime);
+        } else if let JoinHandleState::Polled(waker) = &runtime.running().join_handle {
+            return Some(waker.clone());
         } else if result_is_error {
             let nearest_contained = runtime.nearest_contained(runtime.running_fiber.unwrap());
-            runtime.cancel(nearest_contained);
+            runtime.cancel(nearest_contained, crate::CancellationReason::Panicked);
         }
+
+        None
     });
 
+    // wake up awaiting future, which can't be done while the runtime is borrowed
+    if let Some(waker) = polled {
+        waker.wake();
+    }
+
     // cleanup parent
     tls::runtime(|runtime| {
         let parent_index = runtime.running().parent.unwrap();
@@ -285,19 +806,31 @@ extern "C" fn spawn_trampoline<F: FnOnce() -> T, T>() -> ! {
     // deallocate stack
     tls::runtime(|runtime| {
         if let JoinHandleState::Dropped = runtime.running().join_handle {
-            let stack = runtime.running().stack;
-            runtime.stack_pool.push(stack);
-            runtime.fibers.remove(runtime.running_fiber.unwrap().0);
+            let fiber = runtime.fibers.remove(runtime.running_fiber.unwrap().0);
+            assert!(runtime.dead_stack.is_none());
+            runtime.dead_stack = Some(fiber.stack);
         }
     });
 
     // continue to next fiber
     let mut dummy = mem::MaybeUninit::uninit();
-    let next = tls::runtime(|runtime| runtime.process_io());
+    let next = process_io();
     unsafe { context_switch::jump(dummy.as_mut_ptr(), next) };
     unreachable!()
 }
 
+/// Picks the next fiber to run, waking up futures whose I/O completed along the way.
+fn process_io() -> *const context_switch::Continuation {
+    let mut wakers = Vec::new();
+    loop {
+        if let Some(next) = tls::runtime(|runtime| runtime.process_io(&mut wakers)) {
+            break next;
+        }
+
+        wakers.drain(..).for_each(task::Waker::wake);
+    }
+}
+
 /// Handle for joining or cancelling a fiber.
 #[derive(Debug)]
 pub struct JoinHandle<T> {
@@ -321,10 +854,10 @@ impl<T> JoinHandle<T> {
         }
 
         if is_cancelled() && !tls::runtime(|rt| rt.fibers[self.fiber.0].is_cancelled) {
-            return Err(crate::Error::Cancelled);
+            return Err(cancelled());
         }
 
-        park(|waker| {
+        park_on(WaitingOn::Join(self.fiber.0), |waker| {
             tls::runtime(|runtime| {
                 let fiber = &mut runtime.fibers[self.fiber.0];
                 assert!(!fiber.is_completed);
@@ -338,9 +871,9 @@ impl<T> JoinHandle<T> {
 
         assert!(is_cancelled());
         if !tls::runtime(|rt| rt.fibers[self.fiber.0].is_cancelled) {
-            return Err(crate::Error::Cancelled);
+            return Err(cancelled());
         }
-        park(|_| {}); // woken up by completion
+        park_on(WaitingOn::Join(self.fiber.0), |_| {}); // woken up by completion
 
         self.read_output()
     }
@@ -348,15 +881,20 @@ impl<T> JoinHandle<T> {
     fn read_output(self) -> Result<T, crate::Error<Box<dyn Any + Send>>> {
         tls::runtime(|runtime| {
             let fiber = &mut runtime.fibers[self.fiber.0];
-            let result = unsafe { fiber.stack.union_ref::<thread::Result<T>>().read() };
-            result.map_err(|e| crate::Error::Original(e))
+            let result = unsafe { fiber.stack_base().union_ref::<thread::Result<T>>().read() };
+            result.map_err(crate::Error::Original)
         })
     }
 
     /// ...
     pub fn cancel(&self) {
+        self.cancel_with(crate::CancellationReason::Requested);
+    }
+
+    /// Cancels the fiber and its descendants, recording why.
+    pub fn cancel_with(&self, reason: crate::CancellationReason) {
         tls::runtime(|runtime| {
-            runtime.cancel(self.fiber);
+            runtime.cancel(self.fiber, reason);
         })
     }
 
@@ -364,7 +902,7 @@ impl<T> JoinHandle<T> {
     pub fn cancel_propagating(&self) {
         tls::runtime(|runtime| {
             let nearest_contained = runtime.nearest_contained(self.fiber);
-            runtime.cancel(nearest_contained);
+            runtime.cancel(nearest_contained, crate::CancellationReason::Requested);
         })
     }
 }
@@ -376,9 +914,8 @@ impl<T> Drop for JoinHandle<T> {
             runtime.fibers[self.fiber.0].join_handle = JoinHandleState::Dropped;
 
             if runtime.fibers[self.fiber.0].is_completed {
-                let stack = runtime.fibers[self.fiber.0].stack;
-                runtime.stack_pool.push(stack);
-                runtime.fibers.remove(self.fiber.0);
+                let fiber = runtime.fibers.remove(self.fiber.0);
+                runtime.stack_pool.release(fiber.stack);
             }
         });
     }
@@ -386,19 +923,99 @@ impl<T> Drop for JoinHandle<T> {
 
 /// ...
 pub fn park(schedule: impl FnOnce(Waker)) {
-    let running = tls::runtime(|runtime| runtime.running_fiber.unwrap());
+    park_on(WaitingOn::Waker, schedule);
+}
+
+/// Parks while recording what the fiber is waiting on, for deadlock reports.
+pub(crate) fn park_on(waiting_on: WaitingOn, schedule: impl FnOnce(Waker)) {
+    let running = tls::runtime(|runtime| {
+        runtime.park_tokens += 1;
+        runtime.running().park_token = runtime.park_tokens;
+        runtime.running_fiber.unwrap()
+    });
 
     let waker = Waker(running);
     schedule(waker);
 
     // continue to next fiber
-    let (running, next) = tls::runtime(|runtime| {
-        (
-            &mut runtime.running().continuation as *mut context_switch::Continuation,
-            runtime.process_io(),
-        )
+    let running = tls::runtime(|runtime| {
+        runtime.running().waiting_on = Some(waiting_on);
+        &mut runtime.running().continuation as *mut context_switch::Continuation
     });
+    let next = process_io();
     unsafe { context_switch::jump(running, next) };
+    tls::runtime(|runtime| {
+        runtime.recycle_dead_stack();
+        runtime.running().waiting_on = None;
+    });
+}
+
+/// Every fiber is parked and no I/O is in flight, so none of them can ever be woken up.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Deadlock {
+    /// Fibers that were parked, in no particular order.
+    pub parked: Vec<ParkedFiber>,
+}
+
+impl fmt::Display for Deadlock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "deadlock: every fiber is parked and no I/O is in flight")?;
+
+        for fiber in &self.parked {
+            write!(f, "\n  fiber {}", fiber.id)?;
+            if let Some(parent) = fiber.parent {
+                write!(f, " (child of fiber {parent})")?;
+            }
+            write!(f, " is {}", fiber.waiting_on)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A fiber that was parked when a [Deadlock] was detected.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParkedFiber {
+    /// Identifies the fiber within the runtime, the root fiber is 0.
+    pub id: usize,
+    /// The fiber that spawned it, unless it's the root fiber.
+    pub parent: Option<usize>,
+    /// Why the fiber parked.
+    pub waiting_on: WaitingOn,
+}
+
+/// What a parked fiber is waiting on.
+#[non_exhaustive]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum WaitingOn {
+    /// Someone to schedule the [Waker] passed to [park].
+    Waker,
+    /// A [JoinHandle] to the given fiber to complete.
+    Join(usize),
+    /// Its children to complete, after its own closure returned.
+    Children,
+    /// A syscall to complete.
+    Syscall,
+    /// A message on a channel.
+    Channel,
+    /// A future passed to [block_on] to be woken up.
+    Future,
+    /// A simulated clock to pass its deadline.
+    Sleep,
+}
+
+impl fmt::Display for WaitingOn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WaitingOn::Waker => write!(f, "parked until its waker is scheduled"),
+            WaitingOn::Join(fiber) => write!(f, "joining fiber {fiber}"),
+            WaitingOn::Children => write!(f, "waiting for its children to complete"),
+            WaitingOn::Syscall => write!(f, "waiting for a syscall to complete"),
+            WaitingOn::Channel => write!(f, "receiving from a channel"),
+            WaitingOn::Future => write!(f, "blocking on a future"),
+            WaitingOn::Sleep => write!(f, "sleeping"),
+        }
+    }
 }
 
 /// Handle for scheduling a parked fiber.
@@ -414,6 +1031,14 @@ impl Waker {
         });
     }
 
+    /// Creates a handle for waking up the fiber from other threads.
+    pub fn to_send(&self) -> SendWaker {
+        tls::runtime(|runtime| {
+            let park_token = runtime.fibers[self.0 .0].park_token;
+            SendWaker::new(runtime.inbox.clone(), self.0, park_token)
+        })
+    }
+
     fn schedule_with(self, runtime: &mut RuntimeState) {
         // if !runtime.fibers[self.0 .0].is_scheduled {
         // FIXME: slow
@@ -435,8 +1060,13 @@ pub fn yield_now() {
 
 /// ...
 pub fn cancel() {
+    cancel_with(crate::CancellationReason::Requested);
+}
+
+/// Cancels the running fiber and its descendants, recording why.
+pub fn cancel_with(reason: crate::CancellationReason) {
     tls::runtime(|runtime| {
-        runtime.cancel(runtime.running_fiber.unwrap());
+        runtime.cancel(runtime.running_fiber.unwrap(), reason);
     })
 }
 
@@ -444,34 +1074,134 @@ pub fn cancel() {
 pub fn cancel_propagating() {
     tls::runtime(|runtime| {
         let nearest_contained = runtime.nearest_contained(runtime.running_fiber.unwrap());
-        runtime.cancel(nearest_contained);
+        runtime.cancel(nearest_contained, crate::CancellationReason::Requested);
     })
 }
 
+/// Names the running fiber, which shows up in the watchdog's logs, see [Builder::watchdog].
+pub fn set_name(name: &str) {
+    tls::runtime(|runtime| {
+        let name: Arc<str> = name.into();
+        if let Some(watchdog) = &runtime.watchdog {
+            watchdog.renamed(name.clone());
+        }
+        runtime.running().name = Some(name);
+    });
+}
+
+/// Number of bytes of stack the running fiber has used at its deepest so far.
+/// Returns [None] unless enabled with [Builder::track_stack_usage].
+pub fn stack_usage() -> Option<usize> {
+    tls::runtime(|runtime| {
+        let tracked = runtime.stack_pool.tracks_usage();
+        tracked.then(|| runtime.running().stack.usage())
+    })
+}
+
+/// Number of bytes of stack used by the deepest fiber that's completed so far.
+/// Returns [None] unless enabled with [Builder::track_stack_usage].
+pub fn peak_stack_usage() -> Option<usize> {
+    tls::runtime(|runtime| runtime.stack_pool.peak_usage())
+}
+
 /// ...
 pub fn is_cancelled() -> bool {
     tls::runtime(|runtime| {
         let fiber = runtime.running();
-        fiber.is_cancelled
+        fiber.is_cancelled && !fiber.is_shielded
     })
 }
 
+/// Runs the closure as if the running fiber wasn't cancelled.
+///
+/// Intended for cleanup that must complete, like closing file descriptors or flushing a final response.
+/// Cancellation doesn't interrupt syscalls issued inside the closure, and takes effect again once it returns.
+/// [cancellation_reason] still reports why the fiber was cancelled.
+pub fn shield<T>(f: impl FnOnce() -> T) -> T {
+    struct Unshield(bool);
+
+    impl Drop for Unshield {
+        fn drop(&mut self) {
+            tls::runtime(|runtime| runtime.running().is_shielded = self.0);
+        }
+    }
+
+    let was_shielded =
+        tls::runtime(|runtime| mem::replace(&mut runtime.running().is_shielded, true));
+    let _unshield = Unshield(was_shielded);
+
+    f()
+}
+
+/// Why the running fiber was cancelled, or [None] if it wasn't.
+pub fn cancellation_reason() -> Option<crate::CancellationReason> {
+    tls::runtime(|runtime| runtime.running().cancellation_reason)
+}
+
+/// Whether the current thread is running a runtime.
+pub(crate) fn is_running() -> bool {
+    tls::try_runtime(|_| ()).is_some()
+}
+
+/// Error for an operation that was interrupted by the running fiber's cancellation.
+pub(crate) fn cancelled<E>() -> crate::Error<E> {
+    // also reached from public error conversions, which could run outside a runtime
+    let reason = tls::try_runtime(|runtime| {
+        let fiber = runtime.running_fiber?;
+        runtime.fibers[fiber.0].cancellation_reason
+    });
+    crate::Error::Cancelled(
+        reason
+            .flatten()
+            .unwrap_or(crate::CancellationReason::Requested),
+    )
+}
+
 pub(crate) fn syscall(sqe: io_uring::squeue::Entry) -> crate::IoResult<u32> {
+    syscall_with(sqe, false)
+}
+
+/// Submits the syscall right away if it's likely to complete inline, otherwise it's batched with the others until the fiber parks.
+fn syscall_with(sqe: io_uring::squeue::Entry, is_eager: bool) -> crate::IoResult<u32> {
     if is_cancelled() {
-        return Err(crate::Error::Cancelled);
+        return Err(cancelled());
     }
 
     let fiber_id = tls::runtime(|rt| rt.running_fiber.unwrap());
     let syscall_id = syscall::Id(fiber_id.0 as u64);
 
-    tls::runtime(|runtime| {
+    let mut wakers = Vec::new();
+    let is_completed = tls::runtime(|runtime| {
         let fiber = runtime.running();
         assert!(fiber.syscall_result.is_none());
 
         runtime.kernel.issue(syscall_id, sqe);
+        if !is_eager || runtime.syscall_budget == 0 {
+            return false;
+        }
+
+        // fast path for syscalls that don't have to wait
+        runtime.kernel.submit();
+        runtime.process_completed(&mut wakers);
+        runtime.running().syscall_result.is_some()
     });
+    wakers.into_iter().for_each(task::Waker::wake);
+
+    if is_completed {
+        let is_exhausted = tls::runtime(|runtime| {
+            runtime.syscall_streak += 1;
+            runtime.syscall_streak >= runtime.syscall_budget
+        });
+
+        // don't starve other fibers
+        if is_exhausted {
+            yield_now();
+        }
+
+        return read_syscall_result();
+    }
 
-    park(|_| {}); // woken up by CQE or cancellation
+    park_on(WaitingOn::Syscall, |_| {}); // woken up by CQE or cancellation
 
     if tls::runtime(|rt| rt.running().syscall_result.is_some()) {
         return read_syscall_result();
@@ -479,134 +1209,624 @@ pub(crate) fn syscall(sqe: io_uring::squeue::Entry) -> crate::IoResult<u32> {
 
     assert!(is_cancelled());
     tls::runtime(|rt| rt.kernel.cancel(syscall_id));
-    park(|_| {}); // woken up by CQE
+    park_on(WaitingOn::Syscall, |_| {}); // woken up by CQE
+
+    read_syscall_result()
+}
+
+/// Whether the kernel supports the io_uring opcode, e.g. `io_uring::opcode::Statx::CODE`.
+///
+/// Probed once when the runtime starts.
+pub fn supports(opcode: u8) -> bool {
+    tls::runtime(|runtime| {
+        #[cfg(test)]
+        if runtime.unsupported_opcodes.contains(&opcode) {
+            return false;
+        }
+
+        runtime.kernel.is_supported(opcode)
+    })
+}
+
+/// Pretends that the kernel doesn't support the opcode, to test fallbacks.
+#[cfg(test)]
+pub(crate) fn pretend_unsupported(opcode: u8) {
+    tls::runtime(|runtime| runtime.unsupported_opcodes.push(opcode));
+}
+
+/// Like [syscall], but runs the equivalent blocking syscall on a helper thread if the kernel doesn't support the opcode.
+///
+/// The fallback returns the raw libc result, where -1 means that errno is set.
+/// It can't be interrupted by cancellation.
+pub(crate) fn syscall_or_unblock(
+    opcode: u8,
+    sqe: io_uring::squeue::Entry,
+    fallback: impl FnOnce() -> i64 + Send,
+) -> crate::IoResult<u32> {
+    if supports(opcode) {
+        return syscall_with(sqe, completes_inline(opcode));
+    }
+
+    if is_cancelled() {
+        return Err(cancelled());
+    }
+
+    // errno is thread local, so it's read on the helper thread
+    let result = blocking::unblock(|| errno_result(fallback()));
+    tls::runtime(|runtime| runtime.running().syscall_result = Some(result));
 
     read_syscall_result()
 }
 
+/// Whether the opcode usually completes as soon as it's submitted, e.g. file I/O served from the page cache.
+///
+/// Socket I/O goes through [syscall_or_poll] instead, which is always batched.
+fn completes_inline(opcode: u8) -> bool {
+    use io_uring::opcode::*;
+
+    matches!(
+        opcode,
+        Nop::CODE
+            | Read::CODE
+            | Write::CODE
+            | Readv::CODE
+            | Writev::CODE
+            | ReadFixed::CODE
+            | WriteFixed::CODE
+            | Statx::CODE
+            | OpenAt::CODE
+            | OpenAt2::CODE
+            | Close::CODE
+            | Fsync::CODE
+            | SyncFileRange::CODE
+            | Fallocate::CODE
+            | Fadvise::CODE
+            | RenameAt::CODE
+            | UnlinkAt::CODE
+            | MkDirAt::CODE
+            | SymlinkAt::CODE
+            | LinkAt::CODE
+    )
+}
+
+/// Like [syscall], but runs the equivalent non-blocking syscall if the kernel doesn't support the opcode.
+///
+/// Intended for sockets, which could block a helper thread indefinitely.
+/// The fallback is retried whenever the file descriptor becomes ready for the events, until it stops failing with `EAGAIN`.
+pub(crate) fn syscall_or_poll(
+    opcode: u8,
+    sqe: io_uring::squeue::Entry,
+    fd: RawFd,
+    events: u32,
+    mut fallback: impl FnMut() -> i64,
+) -> crate::IoResult<u32> {
+    if supports(opcode) {
+        return syscall(sqe);
+    }
+
+    loop {
+        if is_cancelled() {
+            return Err(cancelled());
+        }
+
+        let result = errno_result(fallback());
+        if result != -libc::EAGAIN {
+            tls::runtime(|runtime| runtime.running().syscall_result = Some(result));
+            return read_syscall_result();
+        }
+
+        poll(fd, events)?;
+    }
+}
+
+/// Converts a libc result into the same form as a completion's result.
+fn errno_result(result: i64) -> i32 {
+    match result {
+        -1 => -io::Error::last_os_error().raw_os_error().unwrap(),
+        result => result as i32,
+    }
+}
+
+/// Kernel internal error that can leak out of interrupted io_uring operations, like `Splice`.
+const ERESTARTSYS: i32 = 512;
+
 fn read_syscall_result() -> crate::IoResult<u32> {
     let result = tls::runtime(|rt| rt.running().syscall_result.take()).unwrap();
 
-    if result >= 0 {
-        Ok(result as u32)
-    } else {
-        if -result == libc::ECANCELED {
-            return Err(crate::Error::Cancelled);
+    if result >= 0 {
+        Ok(result as u32)
+    } else {
+        if -result == libc::ECANCELED {
+            return Err(cancelled());
+        }
+
+        // cancelling an operation that's blocked in a kernel worker interrupts it instead
+        if matches!(-result, libc::EINTR | ERESTARTSYS) && is_cancelled() {
+            return Err(cancelled());
+        }
+
+        let error = io::Error::from_raw_os_error(-result);
+        Err(crate::Error::Original(error))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::*;
+
+    mod start {
+        use std::time::Duration;
+
+        use super::*;
+
+        #[test]
+        fn returns_output() {
+            let output = start(|| 123);
+
+            assert_eq!(output.unwrap(), 123);
+        }
+
+        #[test]
+        fn catches_panic() {
+            let result = start(|| panic!());
+
+            assert!(result.is_err());
+        }
+
+        #[test]
+        #[should_panic]
+        fn cant_nest() {
+            start(|| {
+                start(|| {}).unwrap();
+            })
+            .unwrap();
+        }
+
+        #[test]
+        fn works_several_times() {
+            start(|| {}).unwrap();
+            start(|| {}).unwrap();
+        }
+
+        #[test]
+        fn works_in_parallel() {
+            let handle = thread::spawn(|| {
+                start(|| thread::sleep(Duration::from_millis(2))).unwrap();
+            });
+
+            start(|| {
+                thread::sleep(Duration::from_millis(1));
+            })
+            .unwrap();
+
+            handle.join().unwrap();
+        }
+
+        #[test]
+        fn waits_for_dropped_child() {
+            let before = Instant::now();
+
+            start(|| {
+                let handle = spawn(|| crate::time::sleep(Duration::from_millis(5)));
+                drop(handle);
+            })
+            .unwrap();
+
+            assert!(before.elapsed() > Duration::from_millis(5));
+        }
+
+        #[test]
+        fn waits_for_forgotten_child() {
+            let before = Instant::now();
+
+            start(|| {
+                let handle = spawn(|| crate::time::sleep(Duration::from_millis(5)));
+                mem::forget(handle);
+            })
+            .unwrap();
+
+            assert!(before.elapsed() > Duration::from_millis(5));
+        }
+
+        #[test]
+        #[ignore]
+        fn cleans_up_after_itself() {
+            // enough to hit OS limits
+            for _ in 0..1_000_000 {
+                start(|| {}).unwrap();
+            }
+        }
+
+        mod cancellation {
+            use super::*;
+
+            #[test]
+            fn initially_not_cancelled() {
+                start(|| {
+                    assert!(!is_cancelled());
+                })
+                .unwrap();
+            }
+
+            #[test]
+            fn cancelled_after_cancelling_self() {
+                start(|| {
+                    cancel();
+                    assert!(is_cancelled());
+                })
+                .unwrap();
+            }
+        }
+    }
+
+    mod builder {
+        use super::*;
+
+        #[test]
+        fn returns_output() {
+            let output = Builder::new().start(|| 123);
+
+            assert_eq!(output.unwrap(), 123);
+        }
+
+        #[test]
+        fn grows_stack_to_configured_size() {
+            Builder::new()
+                .stack_size(1024 * 1024)
+                .start(|| {
+                    let buffer = hint::black_box([1_u8; 256 * 1024]);
+                    assert_eq!(buffer[0], 1);
+                })
+                .unwrap();
+        }
+
+        #[test]
+        fn works_with_small_ring() {
+            Builder::new()
+                .ring_entries(2)
+                .start(|| {
+                    let handles: Vec<_> = (0..8)
+                        .map(|_| spawn(|| crate::time::sleep(Duration::from_millis(1)).unwrap()))
+                        .collect();
+                    handles
+                        .into_iter()
+                        .for_each(|handle| handle.join().unwrap());
+                })
+                .unwrap();
+        }
+
+        #[test]
+        fn starts_runtime_per_core() {
+            let cores = thread::available_parallelism().unwrap().get();
+
+            let outputs = Builder::new().start_per_core(|| {
+                spawn(|| thread::current().name().unwrap().to_string())
+                    .join()
+                    .unwrap()
+            });
+
+            let names: BTreeSet<_> = outputs.into_iter().map(Result::unwrap).collect();
+            assert_eq!(names.len(), cores);
+        }
+
+        #[test]
+        fn pins_within_affinity_mask() {
+            fn allowed_cores() -> Vec<usize> {
+                unsafe {
+                    let mut set: libc::cpu_set_t = mem::zeroed();
+                    let size = mem::size_of::<libc::cpu_set_t>();
+                    assert_eq!(libc::sched_getaffinity(0, size, &mut set), 0);
+                    (0..libc::CPU_SETSIZE as usize)
+                        .filter(|&core| libc::CPU_ISSET(core, &set))
+                        .collect()
+                }
+            }
+
+            let allowed = allowed_cores();
+            let last = allowed.len() - 1;
+
+            let pinned = thread::spawn(move || {
+                pin_to_core(last);
+                allowed_cores()
+            })
+            .join()
+            .unwrap();
+
+            assert_eq!(pinned, [allowed[last]]);
         }
 
-        let error = io::Error::from_raw_os_error(-result);
-        Err(crate::Error::Original(error))
-    }
-}
+        #[test]
+        fn untracked_stack_usage() {
+            start(|| {
+                assert_eq!(stack_usage(), None);
+                assert_eq!(peak_stack_usage(), None);
+            })
+            .unwrap();
+        }
 
-#[cfg(test)]
-mod tests {
-    use std::time::{Duration, Instant};
+        #[test]
+        fn tracks_stack_usage() {
+            Builder::new()
+                .track_stack_usage(true)
+                .start(|| {
+                    let before = stack_usage().unwrap();
+
+                    spawn(|| hint::black_box([1_u8; 16 * 1024])[0])
+                        .join()
+                        .unwrap();
+
+                    assert!(stack_usage().unwrap() >= before);
+                    assert!(peak_stack_usage().unwrap() > 16 * 1024);
+                })
+                .unwrap();
+        }
 
-    use super::*;
+        #[test]
+        fn reuses_stacks_past_pool_limits() {
+            Builder::new()
+                .stack_pool_capacity(2)
+                .dirty_stack_limit(1)
+                .start(|| {
+                    let handles: Vec<_> = (0..4).map(|i| spawn(move || i)).collect();
+                    for (i, handle) in handles.into_iter().enumerate() {
+                        assert_eq!(handle.join().unwrap(), i);
+                    }
 
-    mod start {
-        use std::time::Duration;
+                    spawn(|| {}).join().unwrap();
+                })
+                .unwrap();
+        }
+    }
 
+    mod deadlock {
         use super::*;
 
         #[test]
-        fn returns_output() {
-            let output = start(|| 123);
+        #[should_panic(expected = "deadlock")]
+        fn panics_by_default() {
+            let _ = start(|| park(|_| {}));
+        }
 
-            assert_eq!(output.unwrap(), 123);
+        #[test]
+        fn calls_hook() {
+            let result = Builder::new().on_deadlock(|_| {}).start(|| park(|_| {}));
+
+            let deadlock = result.unwrap_err().downcast::<Deadlock>().unwrap();
+            assert_eq!(
+                deadlock.parked,
+                vec![ParkedFiber {
+                    id: 0,
+                    parent: None,
+                    waiting_on: WaitingOn::Waker,
+                }]
+            );
         }
 
         #[test]
-        fn catches_panic() {
-            let result = start(|| panic!());
+        fn reports_what_fibers_wait_on() {
+            let result = Builder::new().on_deadlock(|_| {}).start(|| {
+                let (_tx, rx) = crate::sync::channel::unbounded::<()>();
+                let handle = spawn(move || rx.recv().unwrap());
+                handle.join().unwrap();
+            });
 
-            assert!(result.is_err());
+            let deadlock = result.unwrap_err().downcast::<Deadlock>().unwrap();
+            let mut parked = deadlock.parked.clone();
+            parked.sort_by_key(|fiber| fiber.id);
+            assert_eq!(parked[0].waiting_on, WaitingOn::Join(1));
+            assert_eq!(parked[1].waiting_on, WaitingOn::Channel);
+            assert_eq!(parked[1].parent, Some(0));
+            assert!(deadlock.to_string().contains("receiving from a channel"));
         }
 
         #[test]
-        #[should_panic]
-        fn cant_nest() {
+        fn not_detected_while_syscall_in_flight() {
             start(|| {
-                start(|| {}).unwrap();
+                let handle = spawn(|| crate::time::sleep(Duration::from_millis(5)));
+                handle.join().unwrap().unwrap();
             })
             .unwrap();
         }
 
         #[test]
-        fn works_several_times() {
-            start(|| {}).unwrap();
-            start(|| {}).unwrap();
+        fn detected_after_send_waker_dropped() {
+            let result = Builder::new().on_deadlock(|_| {}).start(|| {
+                park(|waker| {
+                    let waker = waker.to_send();
+                    std::thread::spawn(move || drop(waker));
+                });
+            });
+
+            assert!(result.unwrap_err().downcast::<Deadlock>().is_ok());
+        }
+    }
+
+    mod syscall_budget {
+        use super::*;
+
+        fn nop() {
+            let sqe = io_uring::opcode::Nop::new().build();
+            syscall_or_unblock(io_uring::opcode::Nop::CODE, sqe, || 0).unwrap();
         }
 
         #[test]
-        fn works_in_parallel() {
-            let handle = thread::spawn(|| {
-                start(|| thread::sleep(Duration::from_millis(2))).unwrap();
-            });
+        fn yields_once_exhausted() {
+            Builder::new()
+                .syscall_budget(2)
+                .start(|| {
+                    let is_run = std::rc::Rc::new(std::cell::Cell::new(false));
+                    spawn({
+                        let is_run = is_run.clone();
+                        move || is_run.set(true)
+                    });
+
+                    nop();
+                    assert!(!is_run.get());
+
+                    nop();
+                    assert!(is_run.get());
+                })
+                .unwrap();
+        }
 
+        #[test]
+        fn parks_on_syscalls_that_dont_complete_inline() {
             start(|| {
-                thread::sleep(Duration::from_millis(1));
+                let is_run = std::rc::Rc::new(std::cell::Cell::new(false));
+                spawn({
+                    let is_run = is_run.clone();
+                    move || is_run.set(true)
+                });
+
+                syscall(io_uring::opcode::Nop::new().build()).unwrap();
+                assert!(is_run.get());
             })
             .unwrap();
+        }
 
-            handle.join().unwrap();
+        #[test]
+        fn parks_on_every_syscall_without_budget() {
+            Builder::new()
+                .syscall_budget(0)
+                .start(|| {
+                    let is_run = std::rc::Rc::new(std::cell::Cell::new(false));
+                    spawn({
+                        let is_run = is_run.clone();
+                        move || is_run.set(true)
+                    });
+
+                    nop();
+                    assert!(is_run.get());
+                })
+                .unwrap();
         }
 
         #[test]
-        fn waits_for_dropped_child() {
-            let before = Instant::now();
+        fn watchdog_tolerates_busy_fiber() {
+            static REPORTS: std::sync::Mutex<Vec<String>> = std::sync::Mutex::new(Vec::new());
+
+            Builder::new()
+                .watchdog(Duration::from_millis(1))
+                .on_watchdog_report(|report| REPORTS.lock().unwrap().push(report.to_string()))
+                .start(|| {
+                    set_name("busy");
+                    let start = Instant::now();
+                    while start.elapsed() < Duration::from_millis(10) {
+                        hint::spin_loop();
+                    }
+                    yield_now();
+                })
+                .unwrap();
+
+            let reports = REPORTS.lock().unwrap();
+            assert!(reports
+                .iter()
+                .any(|report| report.contains("(busy) has run for")));
+            assert!(reports
+                .iter()
+                .any(|report| report.contains("(busy) finally yielded at")));
+        }
+    }
+
+    mod supports {
+        use super::*;
 
+        #[test]
+        fn probes_opcodes() {
             start(|| {
-                let handle = spawn(|| crate::time::sleep(Duration::from_millis(5)));
-                drop(handle);
+                assert!(supports(io_uring::opcode::Nop::CODE));
+                assert!(!supports(u8::MAX));
             })
             .unwrap();
-
-            assert!(before.elapsed() > Duration::from_millis(5));
         }
 
         #[test]
-        fn waits_for_forgotten_child() {
-            let before = Instant::now();
-
+        fn unblocks_fallback_after_cancellation() {
             start(|| {
-                let handle = spawn(|| crate::time::sleep(Duration::from_millis(5)));
-                mem::forget(handle);
+                pretend_unsupported(io_uring::opcode::Nop::CODE);
+                let sqe = io_uring::opcode::Nop::new().build();
+
+                let handle = spawn(move || {
+                    syscall_or_unblock(io_uring::opcode::Nop::CODE, sqe, || {
+                        std::thread::sleep(Duration::from_millis(5));
+                        123
+                    })
+                });
+                yield_now();
+                handle.cancel();
+
+                // already running on the helper thread, so it completes
+                assert_eq!(handle.join().unwrap().unwrap(), 123);
             })
             .unwrap();
+        }
 
-            assert!(before.elapsed() > Duration::from_millis(5));
+        #[test]
+        fn root_returns_while_unblocked() {
+            start(|| {
+                pretend_unsupported(io_uring::opcode::Nop::CODE);
+                let sqe = io_uring::opcode::Nop::new().build();
+
+                spawn(move || {
+                    let mut borrowed = [0_u8; 64];
+                    syscall_or_unblock(io_uring::opcode::Nop::CODE, sqe, || {
+                        std::thread::sleep(Duration::from_millis(5));
+                        borrowed.fill(1); // still on the fiber's stack
+                        0
+                    })
+                    .unwrap();
+                    assert_eq!(borrowed, [1; 64]);
+                });
+                yield_now();
+            })
+            .unwrap();
         }
+    }
+
+    mod send_waker {
+        use super::*;
 
         #[test]
-        #[ignore]
-        fn cleans_up_after_itself() {
-            // enough to hit OS limits
-            for _ in 0..1_000_000 {
-                start(|| {}).unwrap();
-            }
+        fn wakes_from_other_thread() {
+            start(|| {
+                park(|waker| {
+                    let waker = waker.to_send();
+                    std::thread::spawn(move || {
+                        std::thread::sleep(Duration::from_millis(1));
+                        waker.wake();
+                    });
+                });
+            })
+            .unwrap();
         }
 
-        mod cancellation {
-            use super::*;
+        #[test]
+        fn ignores_stale_wake_up() {
+            start(|| {
+                let mut stale = None;
+                park(|waker| {
+                    stale = Some(waker.to_send());
+                    waker.schedule();
+                });
+                stale.unwrap().wake();
 
-            #[test]
-            fn initially_not_cancelled() {
-                start(|| {
-                    assert!(!is_cancelled());
-                })
-                .unwrap();
-            }
+                let parked = std::rc::Rc::new(std::cell::RefCell::new(None::<Waker>));
+                let is_woken = std::rc::Rc::new(std::cell::Cell::new(false));
+                spawn({
+                    let (parked, is_woken) = (parked.clone(), is_woken.clone());
+                    move || {
+                        yield_now();
+                        assert!(!is_woken.get());
+                        parked.take().unwrap().schedule();
+                    }
+                });
 
-            #[test]
-            fn cancelled_after_cancelling_self() {
-                start(|| {
-                    cancel();
-                    assert!(is_cancelled());
-                })
-                .unwrap();
-            }
+                park(|waker| *parked.borrow_mut() = Some(waker));
+                is_woken.set(true);
+            })
+            .unwrap();
         }
     }
 
@@ -890,6 +2110,74 @@ mod tests {
                 .unwrap();
             }
 
+            #[test]
+            fn initially_no_reason() {
+                start(|| {
+                    assert_eq!(cancellation_reason(), None);
+                })
+                .unwrap();
+            }
+
+            #[test]
+            fn converts_io_error_outside_runtime() {
+                let error = std::io::Error::from_raw_os_error(libc::ECANCELED);
+                let error = crate::Error::from_io_error(error);
+                assert_eq!(
+                    error.map(|_| ()),
+                    crate::Error::Cancelled(crate::CancellationReason::Requested)
+                );
+
+                let error = std::io::Error::other("custom");
+                let error = crate::Error::from_io_error(error);
+                assert!(matches!(error, crate::Error::Original(_)));
+            }
+
+            #[test]
+            fn reason_propagates_to_children() {
+                start(|| {
+                    let handle = spawn(|| {
+                        let grandchild = spawn(cancellation_reason);
+                        grandchild.join().unwrap()
+                    });
+
+                    handle.cancel_with(crate::CancellationReason::Shutdown);
+
+                    let reason = handle.join().unwrap();
+                    assert_eq!(reason, Some(crate::CancellationReason::Shutdown));
+                    assert_eq!(cancellation_reason(), None);
+                })
+                .unwrap();
+            }
+
+            #[test]
+            fn first_reason_sticks() {
+                start(|| {
+                    cancel_with(crate::CancellationReason::Timeout);
+                    cancel_with(crate::CancellationReason::Shutdown);
+
+                    assert_eq!(
+                        cancellation_reason(),
+                        Some(crate::CancellationReason::Timeout)
+                    );
+                })
+                .unwrap();
+            }
+
+            #[test]
+            fn reason_after_dropped_child_panic() {
+                start(|| {
+                    drop(spawn(|| panic!()));
+
+                    yield_now();
+
+                    assert_eq!(
+                        cancellation_reason(),
+                        Some(crate::CancellationReason::Panicked)
+                    );
+                })
+                .unwrap();
+            }
+
             #[test]
             fn cancelled_after_forgotten_child_panic() {
                 start(|| {
@@ -950,12 +2238,34 @@ mod tests {
                         let before = Instant::now();
                         let result = handle.join().unwrap();
 
-                        assert_eq!(result, Err(crate::Error::Cancelled));
+                        assert_eq!(
+                            result,
+                            Err(crate::Error::Cancelled(
+                                crate::CancellationReason::Requested
+                            ))
+                        );
                         assert!(before.elapsed() < Duration::from_millis(5));
                     })
                     .unwrap();
                 }
 
+                #[test]
+                fn fails_with_reason() {
+                    start(|| {
+                        let handle = spawn(|| crate::time::sleep(Duration::from_millis(5)));
+                        yield_now();
+
+                        handle.cancel_with(crate::CancellationReason::Timeout);
+                        let result = handle.join().unwrap();
+
+                        assert_eq!(
+                            result,
+                            Err(crate::Error::Cancelled(crate::CancellationReason::Timeout))
+                        );
+                    })
+                    .unwrap();
+                }
+
                 #[test]
                 fn immediately_fails_new_syscall() {
                     start(|| {
@@ -964,7 +2274,12 @@ mod tests {
                         let before = Instant::now();
                         let result = crate::time::sleep(Duration::from_millis(5));
 
-                        assert_eq!(result, Err(crate::Error::Cancelled));
+                        assert_eq!(
+                            result,
+                            Err(crate::Error::Cancelled(
+                                crate::CancellationReason::Requested
+                            ))
+                        );
                         assert!(before.elapsed() < Duration::from_millis(5));
                     })
                     .unwrap();
@@ -973,6 +2288,90 @@ mod tests {
         }
     }
 
+    mod shield {
+        use super::*;
+
+        #[test]
+        fn not_cancelled_inside() {
+            start(|| {
+                cancel();
+
+                shield(|| assert!(!is_cancelled()));
+
+                assert!(is_cancelled());
+            })
+            .unwrap();
+        }
+
+        #[test]
+        fn keeps_reason_inside() {
+            start(|| {
+                cancel_with(crate::CancellationReason::Shutdown);
+
+                let reason = shield(cancellation_reason);
+
+                assert_eq!(reason, Some(crate::CancellationReason::Shutdown));
+            })
+            .unwrap();
+        }
+
+        #[test]
+        fn issues_syscalls_when_cancelled() {
+            start(|| {
+                cancel();
+
+                let result = shield(|| crate::time::sleep(Duration::from_millis(1)));
+
+                assert_eq!(result, Ok(()));
+            })
+            .unwrap();
+        }
+
+        #[test]
+        fn cancellation_doesnt_interrupt_syscall() {
+            start(|| {
+                let handle = spawn(|| shield(|| crate::time::sleep(Duration::from_millis(5))));
+                yield_now();
+
+                handle.cancel();
+                let before = Instant::now();
+                let result = handle.join().unwrap();
+
+                assert_eq!(result, Ok(()));
+                assert!(before.elapsed() > Duration::from_millis(4));
+            })
+            .unwrap();
+        }
+
+        #[test]
+        fn nests() {
+            start(|| {
+                cancel();
+
+                shield(|| {
+                    shield(|| {});
+                    assert!(!is_cancelled());
+                });
+
+                assert!(is_cancelled());
+            })
+            .unwrap();
+        }
+
+        #[test]
+        fn unshields_after_panic() {
+            start(|| {
+                cancel();
+
+                let result = panic::catch_unwind(|| shield(|| panic!()));
+
+                assert!(result.is_err());
+                assert!(is_cancelled());
+            })
+            .unwrap();
+        }
+    }
+
     mod yield_now {
         use std::cell::RefCell;
         use std::rc::Rc;
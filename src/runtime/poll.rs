@@ -0,0 +1,295 @@
+//! Readiness of arbitrary file descriptors, for driving non-blocking fds that Uringy doesn't wrap.
+//!
+//! Multishot polls stay armed in the kernel, queueing up events until the fiber asks for them.
+
+use std::collections::VecDeque;
+use std::marker;
+use std::os::fd::RawFd;
+
+use super::{park_on, read_syscall_result, syscall, tls, FiberIndex, WaitingOn, Waker};
+
+/// Distinguishes multishot polls from syscalls issued by fibers.
+const MULTISHOT_FLAG: u64 = 1 << 62;
+
+/// Parks until the file descriptor is ready for any of the events, like `poll(2)`.
+///
+/// Events are `libc::POLLIN`, `libc::POLLOUT`, etc.
+/// Returns the events that occurred, which can include `libc::POLLERR` and `libc::POLLHUP` even if they weren't asked for.
+pub fn poll(fd: RawFd, events: u32) -> crate::IoResult<u32> {
+    let sqe = io_uring::opcode::PollAdd::new(io_uring::types::Fd(fd), events).build();
+    syscall(sqe)
+}
+
+/// Parks until the file descriptor is readable.
+pub fn wait_readable(fd: RawFd) -> crate::IoResult<()> {
+    poll(fd, libc::POLLIN as u32)?;
+    Ok(())
+}
+
+/// Parks until the file descriptor is writable.
+pub fn wait_writable(fd: RawFd) -> crate::IoResult<()> {
+    poll(fd, libc::POLLOUT as u32)?;
+    Ok(())
+}
+
+/// Keeps polling the file descriptor for the events, see [Poller].
+///
+/// Cheaper than calling [poll] in a loop, since the poll is only armed once.
+pub fn poll_multishot(fd: RawFd, events: u32) -> Poller {
+    let key = tls::runtime(|runtime| {
+        runtime.multishots.insert(Multishot {
+            fd,
+            events,
+            results: VecDeque::new(),
+            waiter: None,
+            is_armed: false,
+            is_orphaned: false,
+        })
+    });
+
+    Poller {
+        key,
+        _not_send: marker::PhantomData,
+    }
+}
+
+/// Multishot poll returned by [poll_multishot], stops polling once dropped.
+///
+/// Readiness is edge triggered in practice, so the file descriptor should be drained after every event.
+/// It can't be sent to another thread, since its poll belongs to this thread's runtime.
+///
+/// ```compile_fail
+/// fn assert_send<T: Send>() {}
+/// assert_send::<uringy::runtime::Poller>();
+/// ```
+#[derive(Debug)]
+pub struct Poller {
+    key: usize, // key into the runtime's multishots
+    _not_send: marker::PhantomData<*const ()>,
+}
+
+impl Poller {
+    /// Parks until the next time the file descriptor is ready, returning the events that occurred.
+    ///
+    /// Events that occurred since the last call are returned right away.
+    pub fn wait(&mut self) -> crate::IoResult<u32> {
+        loop {
+            if super::is_cancelled() {
+                return Err(super::cancelled());
+            }
+
+            let result = tls::runtime(|runtime| {
+                let running = runtime.running_fiber.unwrap();
+                let multishot = &mut runtime.multishots[self.key];
+
+                if let Some(result) = multishot.results.pop_front() {
+                    return Some(result);
+                }
+
+                // kernel stops multishot polls on its own, e.g. when completions overflow
+                if !multishot.is_armed {
+                    let fd = io_uring::types::Fd(multishot.fd);
+                    let sqe = io_uring::opcode::PollAdd::new(fd, multishot.events)
+                        .multi(true)
+                        .build();
+                    multishot.is_armed = true;
+                    runtime.kernel.issue(multishot_id(self.key), sqe);
+                }
+
+                runtime.multishots[self.key].waiter = Some(running);
+                None
+            });
+
+            if let Some(result) = result {
+                tls::runtime(|runtime| runtime.running().syscall_result = Some(result));
+                return read_syscall_result();
+            }
+
+            park_on(WaitingOn::Syscall, |_| {}); // woken up by CQE or cancellation
+            tls::runtime(|runtime| runtime.multishots[self.key].waiter = None);
+        }
+    }
+}
+
+impl Drop for Poller {
+    fn drop(&mut self) {
+        tls::runtime(|runtime| {
+            let multishot = &mut runtime.multishots[self.key];
+
+            if multishot.is_armed {
+                // cleaned up once the final completion arrives
+                multishot.is_orphaned = true;
+                runtime.kernel.cancel(multishot_id(self.key));
+            } else {
+                runtime.multishots.remove(self.key);
+            }
+        });
+    }
+}
+
+/// State of a multishot poll, owned by the runtime since completions can arrive at any time.
+#[derive(Debug)]
+pub(super) struct Multishot {
+    fd: RawFd,
+    events: u32,
+    results: VecDeque<i32>,
+    waiter: Option<FiberIndex>,
+    is_armed: bool,
+    is_orphaned: bool, // poller was dropped while armed
+}
+
+/// Whether the completion belongs to a multishot poll rather than a fiber.
+pub(super) fn is_multishot(user_data: super::syscall::Id) -> bool {
+    user_data.0 & MULTISHOT_FLAG != 0
+}
+
+fn multishot_id(key: usize) -> super::syscall::Id {
+    super::syscall::Id(key as u64 | MULTISHOT_FLAG)
+}
+
+impl super::RuntimeState {
+    /// Queues up the result of a multishot poll, waking up the fiber waiting for it.
+    pub(super) fn complete_multishot(
+        &mut self,
+        user_data: super::syscall::Id,
+        result: i32,
+        more: bool,
+    ) {
+        let key = (user_data.0 & !MULTISHOT_FLAG) as usize;
+        let multishot = &mut self.multishots[key];
+
+        if !more {
+            multishot.is_armed = false;
+        }
+
+        if multishot.is_orphaned {
+            if !more {
+                self.multishots.remove(key);
+            }
+            return;
+        }
+
+        // rearmed on the next call, unless it stopped because of an error
+        if !more && result == -libc::ECANCELED {
+            return;
+        }
+
+        multishot.results.push_back(result);
+        if let Some(fiber) = multishot.waiter.take() {
+            Waker(fiber).schedule_with(self);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::runtime::{spawn, start};
+
+    use super::*;
+
+    fn pipe() -> (RawFd, RawFd) {
+        let mut fds = [0; 2];
+        assert_eq!(
+            unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_NONBLOCK) },
+            0
+        );
+        (fds[0], fds[1])
+    }
+
+    #[test]
+    fn waits_until_readable() {
+        start(|| {
+            let (r, w) = pipe();
+
+            spawn(move || {
+                crate::time::sleep(Duration::from_millis(1)).unwrap();
+                assert_eq!(unsafe { libc::write(w, b"x".as_ptr().cast(), 1) }, 1);
+            });
+
+            wait_readable(r).unwrap();
+
+            let mut buffer = [0; 1];
+            assert_eq!(unsafe { libc::read(r, buffer.as_mut_ptr().cast(), 1) }, 1);
+            unsafe { libc::close(r) };
+            unsafe { libc::close(w) };
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn empty_pipe_is_writable() {
+        start(|| {
+            let (r, w) = pipe();
+
+            wait_writable(w).unwrap();
+
+            unsafe { libc::close(r) };
+            unsafe { libc::close(w) };
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn reports_hang_up() {
+        start(|| {
+            let (r, w) = pipe();
+            unsafe { libc::close(w) };
+
+            let events = poll(r, libc::POLLIN as u32).unwrap();
+
+            assert_ne!(events & libc::POLLHUP as u32, 0);
+            unsafe { libc::close(r) };
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn polls_repeatedly() {
+        start(|| {
+            let (r, w) = pipe();
+            let mut poller = poll_multishot(r, libc::POLLIN as u32);
+
+            for _ in 0..3 {
+                spawn(move || {
+                    assert_eq!(unsafe { libc::write(w, b"x".as_ptr().cast(), 1) }, 1);
+                });
+
+                let events = poller.wait().unwrap();
+                assert_ne!(events & libc::POLLIN as u32, 0);
+
+                let mut buffer = [0; 1];
+                assert_eq!(unsafe { libc::read(r, buffer.as_mut_ptr().cast(), 1) }, 1);
+            }
+
+            drop(poller);
+            crate::time::sleep(Duration::from_millis(1)).unwrap(); // let cancellation complete
+            unsafe { libc::close(r) };
+            unsafe { libc::close(w) };
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn multishot_fails_when_cancelled() {
+        start(|| {
+            let (r, w) = pipe();
+
+            let handle = spawn(move || poll_multishot(r, libc::POLLIN as u32).wait());
+            crate::time::sleep(Duration::from_millis(1)).unwrap();
+            handle.cancel();
+
+            assert!(matches!(
+                handle.join().unwrap(),
+                Err(crate::Error::Cancelled(
+                    crate::CancellationReason::Requested
+                ))
+            ));
+            crate::time::sleep(Duration::from_millis(1)).unwrap();
+            unsafe { libc::close(r) };
+            unsafe { libc::close(w) };
+        })
+        .unwrap();
+    }
+}
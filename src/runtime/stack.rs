@@ -4,12 +4,20 @@
 //! The stack is protected from overflow using guard pages at the lowest addresses.
 
 use std::num::NonZeroUsize;
+use std::time::{Duration, Instant};
 use std::{ffi, io, ptr};
 
+/// Word written over the usable region of a stack to measure how deep it grows.
+const CANARY: u64 = 0xDEAD_BEEF_CAFE_BABE;
+
+/// How long a stack can sit unused in the pool before it's given back to the kernel.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(5);
+
 #[derive(Debug)]
 pub(super) struct Stack {
     pub(super) pointer: *mut ffi::c_void,
     pub(super) length: usize,
+    guard_length: usize,
 }
 
 impl Stack {
@@ -18,8 +26,9 @@ impl Stack {
         let (guard_pages, usable_pages) = (guard_pages.get(), usable_pages.get());
 
         // page aligned sizes
-        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize };
+        let page_size = page_size();
         let length = (guard_pages + usable_pages) * page_size;
+        let guard_length = guard_pages * page_size;
 
         // kernel allocates an unused block of virtual memory
         let pointer = unsafe {
@@ -38,9 +47,13 @@ impl Stack {
         }
 
         // if guarding memory goes wrong then mmap gets cleaned up in Stack's drop
-        let stack = Stack { pointer, length };
+        let stack = Stack {
+            pointer,
+            length,
+            guard_length,
+        };
 
-        let result = unsafe { libc::mprotect(pointer, guard_pages * page_size, libc::PROT_NONE) };
+        let result = unsafe { libc::mprotect(pointer, guard_length, libc::PROT_NONE) };
         if result == -1 {
             let error = io::Error::last_os_error();
             return Err(error);
@@ -54,6 +67,54 @@ impl Stack {
         // safety: part of same allocation, can't overflow
         unsafe { self.pointer.byte_add(self.length) }
     }
+
+    /// The lowest address that isn't protected by a guard page.
+    fn usable(&self) -> *mut ffi::c_void {
+        // safety: part of same allocation, can't overflow
+        unsafe { self.pointer.byte_add(self.guard_length) }
+    }
+
+    /// Fills the usable region with [CANARY], faulting in every page.
+    fn paint(&mut self) {
+        let words = (self.length - self.guard_length) / std::mem::size_of::<u64>();
+        let usable = self.usable() as *mut u64;
+        for i in 0..words {
+            unsafe { usable.add(i).write_volatile(CANARY) };
+        }
+    }
+
+    /// Number of bytes that have been written since the stack was painted.
+    /// Only meaningful for stacks that were painted with [Stack::paint].
+    pub(super) fn usage(&self) -> usize {
+        let words = (self.length - self.guard_length) / std::mem::size_of::<u64>();
+        let usable = self.usable() as *const u64;
+        let untouched = (0..words)
+            .take_while(|&i| unsafe { usable.add(i).read_volatile() } == CANARY)
+            .count();
+
+        (words - untouched) * std::mem::size_of::<u64>()
+    }
+
+    /// Gives the physical memory backing the usable region back to the kernel.
+    /// The virtual memory stays mapped, so the stack can still be reused.
+    fn release_pages(&mut self) -> io::Result<()> {
+        let length = self.length - self.guard_length;
+
+        // lazily freed, so pages that get reused before memory pressure don't fault again
+        let result = unsafe { libc::madvise(self.usable(), length, libc::MADV_FREE) };
+        if result == 0 {
+            return Ok(());
+        }
+
+        // MADV_FREE isn't supported before Linux 4.5
+        let result = unsafe { libc::madvise(self.usable(), length, libc::MADV_DONTNEED) };
+        if result == -1 {
+            let error = io::Error::last_os_error();
+            return Err(error);
+        }
+
+        Ok(())
+    }
 }
 
 impl Drop for Stack {
@@ -63,6 +124,117 @@ impl Drop for Stack {
     }
 }
 
+/// Recycles stacks of completed fibers, since allocating is relatively expensive.
+///
+/// Dirty stacks keep their pages and are handed out first.
+/// Once there are more than [Pool::dirty_limit] of them, the pages of released stacks are given back to the kernel.
+/// Stacks that sit unused for a while are unmapped entirely.
+#[derive(Debug)]
+pub(super) struct Pool {
+    dirty: Vec<Stack>,
+    clean: Vec<Stack>,
+    usable_pages: NonZeroUsize,
+    capacity: usize,
+    dirty_limit: usize,
+    track_usage: bool,
+    peak_usage: usize,
+    // fewest pooled stacks since the last trim, they haven't been used in that time
+    low_water_mark: usize,
+    last_trim: Instant,
+}
+
+impl Pool {
+    pub(super) fn new(
+        usable_pages: NonZeroUsize,
+        capacity: usize,
+        dirty_limit: usize,
+        track_usage: bool,
+    ) -> Self {
+        Pool {
+            dirty: Vec::new(),
+            clean: Vec::new(),
+            usable_pages,
+            capacity,
+            dirty_limit,
+            track_usage,
+            peak_usage: 0,
+            low_water_mark: 0,
+            last_trim: Instant::now(),
+        }
+    }
+
+    /// Reuses a pooled stack, or allocates a new one.
+    pub(super) fn acquire(&mut self) -> io::Result<Stack> {
+        let stack = self.dirty.pop().or_else(|| self.clean.pop());
+        self.low_water_mark = std::cmp::min(self.low_water_mark, self.len());
+
+        let mut stack = match stack {
+            Some(stack) => stack,
+            None => Stack::new(NonZeroUsize::MIN, self.usable_pages)?,
+        };
+
+        if self.track_usage {
+            stack.paint();
+        }
+
+        Ok(stack)
+    }
+
+    /// Returns a stack that's no longer in use to the pool.
+    pub(super) fn release(&mut self, mut stack: Stack) {
+        if self.track_usage {
+            self.peak_usage = std::cmp::max(self.peak_usage, stack.usage());
+        }
+
+        if self.len() >= self.capacity {
+            return; // unmapped by drop
+        }
+
+        if self.dirty.len() < self.dirty_limit {
+            self.dirty.push(stack);
+        } else if stack.release_pages().is_ok() {
+            self.clean.push(stack);
+        }
+    }
+
+    /// Unmaps stacks that haven't been used since the last trim.
+    /// Cheap enough to call whenever the runtime is idle.
+    pub(super) fn trim(&mut self) {
+        if self.last_trim.elapsed() < IDLE_TIMEOUT {
+            return;
+        }
+
+        // clean stacks go first, since they're more expensive to reuse
+        let mut idle = self.low_water_mark;
+        let clean = std::cmp::min(idle, self.clean.len());
+        self.clean.drain(..clean);
+        idle -= clean;
+        self.dirty.drain(..idle);
+
+        self.low_water_mark = self.len();
+        self.last_trim = Instant::now();
+    }
+
+    /// Deepest stack usage of any released stack, if usage is tracked.
+    pub(super) fn peak_usage(&self) -> Option<usize> {
+        self.track_usage.then_some(self.peak_usage)
+    }
+
+    /// Whether stacks are painted to measure their usage.
+    pub(super) fn tracks_usage(&self) -> bool {
+        self.track_usage
+    }
+
+    fn len(&self) -> usize {
+        self.dirty.len() + self.clean.len()
+    }
+}
+
+/// Size of a memory page in bytes.
+pub(super) fn page_size() -> usize {
+    unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -92,4 +264,90 @@ mod tests {
     //         pointer.write(123);
     //     }
     // }
+
+    #[test]
+    fn measures_usage() {
+        let mut stack = Stack::new(NonZeroUsize::MIN, NonZeroUsize::new(2).unwrap()).unwrap();
+        stack.paint();
+        assert_eq!(stack.usage(), 0);
+
+        unsafe { (stack.base() as *mut u64).sub(100).write(0) };
+
+        assert_eq!(stack.usage(), 100 * 8);
+    }
+
+    #[test]
+    fn released_pages_read_as_zero_or_unchanged() {
+        let mut stack = Stack::new(NonZeroUsize::MIN, NonZeroUsize::MIN).unwrap();
+        let pointer = unsafe { (stack.base() as *mut u64).sub(1) };
+        unsafe { pointer.write(123) };
+
+        stack.release_pages().unwrap();
+
+        let value = unsafe { pointer.read() };
+        assert!(value == 0 || value == 123);
+    }
+
+    mod pool {
+        use super::*;
+
+        #[test]
+        fn reuses_released_stack() {
+            let mut pool = Pool::new(NonZeroUsize::MIN, 1, 1, false);
+            let stack = pool.acquire().unwrap();
+            let pointer = stack.pointer;
+
+            pool.release(stack);
+
+            assert_eq!(pool.acquire().unwrap().pointer, pointer);
+        }
+
+        #[test]
+        fn bounded_by_capacity() {
+            let mut pool = Pool::new(NonZeroUsize::MIN, 1, 1, false);
+            let (first, second) = (pool.acquire().unwrap(), pool.acquire().unwrap());
+
+            pool.release(first);
+            pool.release(second);
+
+            assert_eq!(pool.len(), 1);
+        }
+
+        #[test]
+        fn cleans_stacks_past_dirty_limit() {
+            let mut pool = Pool::new(NonZeroUsize::MIN, 2, 1, false);
+            let (first, second) = (pool.acquire().unwrap(), pool.acquire().unwrap());
+
+            pool.release(first);
+            pool.release(second);
+
+            assert_eq!(pool.dirty.len(), 1);
+            assert_eq!(pool.clean.len(), 1);
+        }
+
+        #[test]
+        fn tracks_peak_usage() {
+            let mut pool = Pool::new(NonZeroUsize::MIN, 1, 1, true);
+            let stack = pool.acquire().unwrap();
+            unsafe { (stack.base() as *mut u64).sub(10).write(0) };
+
+            pool.release(stack);
+
+            assert_eq!(pool.peak_usage(), Some(10 * 8));
+        }
+
+        #[test]
+        fn trims_idle_stacks() {
+            let mut pool = Pool::new(NonZeroUsize::MIN, 2, 1, false);
+            let (first, second) = (pool.acquire().unwrap(), pool.acquire().unwrap());
+            pool.release(first);
+            pool.release(second);
+            pool.low_water_mark = pool.len();
+
+            pool.last_trim -= IDLE_TIMEOUT;
+            pool.trim();
+
+            assert_eq!(pool.len(), 0);
+        }
+    }
 }
@@ -5,9 +5,14 @@
 #[cfg(not(target_os = "linux"))]
 compile_error!("Uringy only supports Linux");
 
+use std::mem;
+use std::os::fd::AsRawFd;
+
 #[cfg(target_os = "linux")]
 pub(super) struct Interface {
     io_uring: io_uring::IoUring,
+    in_flight: usize,
+    probe: Option<io_uring::Probe>, // kernels before 5.6 can't be probed
 }
 
 #[cfg(target_os = "linux")]
@@ -16,11 +21,31 @@ const ASYNC_CANCELLATION_USER_DATA: u64 = u64::MAX;
 #[cfg(target_os = "linux")]
 impl Interface {
     // TODO: optionally reuse kernel workers
-    pub(super) fn new() -> Self {
+    pub(super) fn new(entries: u32) -> Self {
         let mut builder = io_uring::IoUring::builder();
         builder.setup_clamp(); // won't panic if IORING_MAX_ENTRIES is too large
-        let io_uring = builder.build(1024).unwrap();
-        Interface { io_uring }
+        let io_uring = builder.build(entries).unwrap();
+
+        let mut probe = io_uring::Probe::new();
+        let probe = match io_uring.submitter().register_probe(&mut probe) {
+            Ok(()) => Some(probe),
+            Err(_) => None,
+        };
+
+        Interface {
+            io_uring,
+            in_flight: 0,
+            probe,
+        }
+    }
+
+    /// Whether the kernel supports the io_uring opcode.
+    pub(super) fn is_supported(&self, opcode: u8) -> bool {
+        match &self.probe {
+            Some(probe) => probe.is_supported(opcode),
+            // probing was added along with IORING_OP_FALLOCATE, so only older opcodes are available
+            None => opcode < io_uring::opcode::Fallocate::CODE,
+        }
     }
 
     /// ...
@@ -29,12 +54,23 @@ impl Interface {
         // TODO: retry on EINTR (interrupted)
     }
 
+    /// Submits issued syscalls without waiting, some complete right away.
+    pub(super) fn submit(&mut self) {
+        self.io_uring.submit().unwrap();
+    }
+
     /// ...
     /// TODO: give this a closure?
-    pub(super) fn process_completed(&mut self) -> impl Iterator<Item = (Id, i32)> {
+    /// Also returns whether more completions will follow, for multishot operations.
+    pub(super) fn process_completed(&mut self) -> impl Iterator<Item = (Id, i32, bool)> {
         let mut results = vec![]; // TODO: return iterator (to avoid allocating) that mutably borrows io_uring by holding cq
 
         for cqe in self.io_uring.completion() {
+            let more = io_uring::cqueue::more(cqe.flags());
+            if !more {
+                self.in_flight -= 1;
+            }
+
             if cqe.user_data() == ASYNC_CANCELLATION_USER_DATA {
                 continue;
             }
@@ -45,7 +81,7 @@ impl Interface {
             // Storing the selected buffer ID, if one was selected. See BUFFER_SELECT for more info.
             // whether oneshot accepts needs to resubscribe (convert to yet another io::error)
 
-            results.push((syscall_id, cqe.result()));
+            results.push((syscall_id, cqe.result(), more));
         }
 
         results.into_iter()
@@ -64,6 +100,13 @@ impl Interface {
             sq = self.io_uring.submission();
         }
         unsafe { sq.push(&sqe).unwrap() }; // safety: submission queue isn't full
+        self.in_flight += 1;
+    }
+
+    /// Number of issued operations that haven't completed yet.
+    /// When zero, waiting for a completion would block forever.
+    pub(super) fn in_flight(&self) -> usize {
+        self.in_flight
     }
 
     /// ...
@@ -71,6 +114,87 @@ impl Interface {
         let sqe = io_uring::opcode::AsyncCancel::new(target.0).build();
         self.issue(Id(ASYNC_CANCELLATION_USER_DATA), sqe);
     }
+
+    /// Registers an empty table of fixed buffers, whose slots are filled in by [Interface::update_buffer].
+    /// Sparse tables need Linux 5.19.
+    pub(super) fn register_buffer_table(&self, capacity: u32) -> std::io::Result<()> {
+        let table = RsrcRegister {
+            nr: capacity,
+            flags: IORING_RSRC_REGISTER_SPARSE,
+            resv2: 0,
+            data: 0,
+            tags: 0,
+        };
+        self.register(
+            IORING_REGISTER_BUFFERS2,
+            &table as *const _ as _,
+            mem::size_of_val(&table),
+        )
+    }
+
+    /// Replaces a slot in the fixed buffer table, an empty buffer clears it.
+    /// Operations that are already using the previous buffer keep it alive until they complete.
+    pub(super) fn update_buffer(&self, index: u16, buffer: libc::iovec) -> std::io::Result<()> {
+        let update = RsrcUpdate2 {
+            offset: index as u32,
+            resv: 0,
+            data: &buffer as *const libc::iovec as u64,
+            tags: 0,
+            nr: 1,
+            resv2: 0,
+        };
+        self.register(
+            IORING_REGISTER_BUFFERS_UPDATE,
+            &update as *const _ as _,
+            mem::size_of_val(&update),
+        )
+    }
+
+    /// The io_uring crate only registers whole buffer tables, so this calls `io_uring_register(2)` directly.
+    fn register(
+        &self,
+        opcode: u32,
+        argument: *const libc::c_void,
+        size: usize,
+    ) -> std::io::Result<()> {
+        let fd = self.io_uring.as_raw_fd();
+        let result =
+            unsafe { libc::syscall(libc::SYS_io_uring_register, fd, opcode, argument, size) };
+        match result {
+            -1 => Err(std::io::Error::last_os_error()),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+const IORING_REGISTER_BUFFERS2: u32 = 15;
+#[cfg(target_os = "linux")]
+const IORING_REGISTER_BUFFERS_UPDATE: u32 = 16;
+#[cfg(target_os = "linux")]
+const IORING_RSRC_REGISTER_SPARSE: u32 = 1 << 0;
+
+/// `struct io_uring_rsrc_register`
+#[cfg(target_os = "linux")]
+#[repr(C)]
+struct RsrcRegister {
+    nr: u32,
+    flags: u32,
+    resv2: u64,
+    data: u64,
+    tags: u64,
+}
+
+/// `struct io_uring_rsrc_update2`
+#[cfg(target_os = "linux")]
+#[repr(C)]
+struct RsrcUpdate2 {
+    offset: u32,
+    resv: u32,
+    data: u64,
+    tags: u64,
+    nr: u32,
+    resv2: u32,
 }
 
 #[repr(transparent)]
@@ -13,16 +13,16 @@ struct Runtime(RefCell<Option<super::RuntimeState>>);
 #[cfg(not(feature = "fast_thread_local"))]
 thread_local! {
     /// Each thread gets its own independent runtime.
-    static RUNTIME: Runtime = Runtime(RefCell::new(None));
+    static RUNTIME: Runtime = const { Runtime(RefCell::new(None)) };
 }
 
-/// Provides a runtime for the duration of the closure. 
+/// Provides a runtime for the duration of the closure.
 #[cfg(not(feature = "fast_thread_local"))]
-pub(super) fn exclusive_runtime<T>(f: impl FnOnce() -> T) -> T {
+pub(super) fn exclusive_runtime<T>(runtime: super::RuntimeState, f: impl FnOnce() -> T) -> T {
     RUNTIME.with(|thread_local| {
         let mut cell = thread_local.0.borrow_mut();
         assert!(cell.is_none(), "can't nest runtimes ...");
-        *cell = Some(super::RuntimeState::new());
+        *cell = Some(runtime);
     });
 
     let output = f();
@@ -45,17 +45,23 @@ pub(super) fn runtime<T>(f: impl FnOnce(&mut super::RuntimeState) -> T) -> T {
     })
 }
 
+/// Like [runtime], but doesn't panic if there's no runtime.
+#[cfg(not(feature = "fast_thread_local"))]
+pub(super) fn try_runtime<T>(f: impl FnOnce(&mut super::RuntimeState) -> T) -> Option<T> {
+    RUNTIME.with(|thread_local| thread_local.0.borrow_mut().as_mut().map(f))
+}
+
 #[cfg(feature = "fast_thread_local")]
 #[thread_local]
 static RUNTIME: Runtime = Runtime(RefCell::new(None));
 
 /// Provides a runtime for the duration of the closure.
 #[cfg(feature = "fast_thread_local")]
-pub(super) fn exclusive_runtime<T>(f: impl FnOnce() -> T) -> T {
+pub(super) fn exclusive_runtime<T>(runtime: super::RuntimeState, f: impl FnOnce() -> T) -> T {
     {
         let mut cell = RUNTIME.0.borrow_mut();
         assert!(cell.is_none(), "...");
-        *cell = Some(super::RuntimeState::new());
+        *cell = Some(runtime);
     }
 
     let output = f();
@@ -73,3 +79,9 @@ pub(super) fn runtime<T>(f: impl FnOnce(&mut super::RuntimeState) -> T) -> T {
     let runtime = cell.as_mut().expect("no runtime...");
     f(runtime)
 }
+
+/// Like [runtime], but doesn't panic if there's no runtime.
+#[cfg(feature = "fast_thread_local")]
+pub(super) fn try_runtime<T>(f: impl FnOnce(&mut super::RuntimeState) -> T) -> Option<T> {
+    RUNTIME.0.borrow_mut().as_mut().map(f)
+}
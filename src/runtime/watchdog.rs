@@ -0,0 +1,156 @@
+//! Detection of fibers that run for too long without yielding.
+//!
+//! A background thread checks that the runtime keeps switching between fibers.
+//! The runtime's thread can't be inspected from the outside, so the offending fiber's backtrace is logged once it yields.
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use std::{io, thread};
+
+#[derive(Debug)]
+pub(super) struct Watchdog {
+    shared: Arc<Shared>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+#[derive(Debug)]
+struct Shared {
+    threshold: Duration,
+    switches: AtomicU64,
+    is_idle: AtomicBool, // waiting for I/O isn't starvation
+    is_reported: AtomicBool,
+    is_stopped: AtomicBool,
+    running: Mutex<Fiber>,
+    report: fn(&str),
+}
+
+#[derive(Debug, Default)]
+struct Fiber {
+    id: usize,
+    name: Option<Arc<str>>,
+}
+
+impl Watchdog {
+    pub(super) fn new(threshold: Duration, report: fn(&str)) -> io::Result<Self> {
+        let shared = Arc::new(Shared {
+            threshold,
+            switches: AtomicU64::new(0),
+            is_idle: AtomicBool::new(false),
+            is_reported: AtomicBool::new(false),
+            is_stopped: AtomicBool::new(false),
+            running: Mutex::new(Fiber::default()),
+            report,
+        });
+
+        let thread = thread::Builder::new()
+            .name("uringy-watchdog".to_string())
+            .spawn({
+                let shared = shared.clone();
+                move || shared.watch()
+            })?;
+
+        Ok(Watchdog {
+            shared,
+            thread: Some(thread),
+        })
+    }
+
+    /// Records that the runtime switched to another fiber.
+    /// Must be called on the stack of the fiber that's switched away from, so its backtrace can be logged.
+    pub(super) fn switched(&self, id: usize, name: Option<Arc<str>>) {
+        if self.shared.is_reported.swap(false, Ordering::AcqRel) {
+            let fiber = self.shared.running.lock().unwrap();
+            let backtrace = std::backtrace::Backtrace::force_capture();
+            (self.shared.report)(&format!("{fiber} finally yielded at:\n{backtrace}"));
+        }
+
+        *self.shared.running.lock().unwrap() = Fiber { id, name };
+        self.shared.switches.fetch_add(1, Ordering::Release);
+    }
+
+    /// Records the running fiber's new name.
+    pub(super) fn renamed(&self, name: Arc<str>) {
+        self.shared.running.lock().unwrap().name = Some(name);
+    }
+
+    /// Records whether the runtime is waiting for I/O.
+    pub(super) fn idle(&self, is_idle: bool) {
+        self.shared.is_idle.store(is_idle, Ordering::Release);
+        self.shared.switches.fetch_add(1, Ordering::Release);
+    }
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        self.shared.is_stopped.store(true, Ordering::Release);
+        let thread = self.thread.take().unwrap();
+        thread.thread().unpark();
+        let _ = thread.join();
+    }
+}
+
+impl Shared {
+    fn watch(&self) {
+        let mut last_switches = self.switches.load(Ordering::Acquire);
+        let mut since = Instant::now();
+
+        while !self.is_stopped.load(Ordering::Acquire) {
+            thread::park_timeout(self.threshold / 4);
+
+            let switches = self.switches.load(Ordering::Acquire);
+            if switches != last_switches || self.is_idle.load(Ordering::Acquire) {
+                last_switches = switches;
+                since = Instant::now();
+                continue;
+            }
+
+            // only reported once per offence
+            let elapsed = since.elapsed();
+            if elapsed >= self.threshold && !self.is_reported.swap(true, Ordering::AcqRel) {
+                let fiber = self.running.lock().unwrap();
+                (self.report)(&format!("{fiber} has run for {elapsed:?} without yielding"));
+            }
+        }
+    }
+}
+
+/// The default report, written to stderr.
+pub(super) fn log(report: &str) {
+    eprintln!("uringy: {report}");
+}
+
+impl std::fmt::Display for Fiber {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "fiber {}", self.id)?;
+        if let Some(name) = &self.name {
+            write!(f, " ({name})")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_fiber_that_doesnt_yield() {
+        let watchdog = Watchdog::new(Duration::from_millis(4), log).unwrap();
+        watchdog.switched(1, Some("busy".into()));
+
+        thread::sleep(Duration::from_millis(20));
+
+        assert!(watchdog.shared.is_reported.load(Ordering::Acquire));
+    }
+
+    #[test]
+    fn ignores_idle_runtime() {
+        let watchdog = Watchdog::new(Duration::from_millis(4), log).unwrap();
+        watchdog.idle(true);
+
+        thread::sleep(Duration::from_millis(20));
+
+        assert!(!watchdog.shared.is_reported.load(Ordering::Acquire));
+    }
+}
@@ -119,10 +119,10 @@ impl<T> Receiver<T> {
 
             if is_cancelled() {
                 println!("recv: cancelled");
-                return Err(crate::Error::Cancelled);
+                return Err(runtime::cancelled());
             }
 
-            runtime::park(|waker| {
+            runtime::park_on(runtime::WaitingOn::Channel, |waker| {
                 state.no_longer_empty.push_back(waker);
                 drop(state);
             }); // woken up by sender or cancellation
@@ -294,7 +294,12 @@ mod tests {
                 handle.cancel();
                 let result = handle.join().unwrap();
 
-                assert_eq!(result, Err(crate::Error::Cancelled));
+                assert_eq!(
+                    result,
+                    Err(crate::Error::Cancelled(
+                        crate::CancellationReason::Requested
+                    ))
+                );
             })
             .unwrap();
         }
@@ -307,7 +312,12 @@ mod tests {
                 cancel();
 
                 assert_eq!(rx.recv(), Ok(1));
-                assert_eq!(rx.recv(), Err(crate::Error::Cancelled));
+                assert_eq!(
+                    rx.recv(),
+                    Err(crate::Error::Cancelled(
+                        crate::CancellationReason::Requested
+                    ))
+                );
             })
             .unwrap();
         }
@@ -1,9 +1,13 @@
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use crate::{runtime, Error};
 
 /// Puts the current fiber to sleep for at least [duration].
 pub fn sleep(duration: Duration) -> crate::CancellableResult<()> {
+    if runtime::is_clock_simulated() {
+        return runtime::sleep_simulated(duration);
+    }
+
     let timespec = io_uring::types::Timespec::from(duration);
     let sqe = io_uring::opcode::Timeout::new(&timespec).build();
     let result = runtime::syscall(sqe);
@@ -12,13 +16,20 @@ pub fn sleep(duration: Duration) -> crate::CancellableResult<()> {
         Ok(_) => unreachable!(),
         Err(error) => match error {
             Error::Original(e) => assert_eq!(e.raw_os_error().unwrap(), libc::ETIME),
-            Error::Cancelled => return Err(Error::Cancelled),
+            Error::Cancelled(reason) => return Err(Error::Cancelled(reason)),
         },
     }
 
     Ok(())
 }
 
+/// The current time, according to the runtime's clock.
+///
+/// Same as [Instant::now], unless the runtime was started with [runtime::Builder::simulated_clock].
+pub fn now() -> Instant {
+    runtime::now()
+}
+
 #[cfg(test)]
 mod tests {
     use std::time::Instant;
@@ -53,5 +64,56 @@ mod tests {
             })
             .unwrap();
         }
+
+        #[test]
+        fn jumps_simulated_clock() {
+            runtime::Builder::new()
+                .simulated_clock(true)
+                .start(|| {
+                    let (before, real_before) = (now(), Instant::now());
+
+                    sleep(Duration::from_secs(60)).unwrap();
+
+                    assert_eq!(now() - before, Duration::from_secs(60));
+                    assert!(real_before.elapsed() < Duration::from_secs(1));
+                })
+                .unwrap();
+        }
+
+        #[test]
+        fn wakes_simulated_sleepers_in_order() {
+            runtime::Builder::new()
+                .simulated_clock(true)
+                .start(|| {
+                    let (tx, rx) = crate::sync::channel::unbounded();
+                    for seconds in [3, 1, 2] {
+                        let tx = tx.clone();
+                        runtime::spawn(move || {
+                            sleep(Duration::from_secs(seconds)).unwrap();
+                            tx.send(seconds).unwrap();
+                        });
+                    }
+                    drop(tx);
+
+                    let order: Vec<_> = std::iter::from_fn(|| rx.recv().ok()).collect();
+                    assert_eq!(order, vec![1, 2, 3]);
+                })
+                .unwrap();
+        }
+
+        #[test]
+        fn cancels_simulated_sleep() {
+            runtime::Builder::new()
+                .simulated_clock(true)
+                .start(|| {
+                    let handle = runtime::spawn(|| sleep(Duration::from_secs(60)));
+                    runtime::yield_now();
+
+                    handle.cancel();
+
+                    assert!(handle.join().unwrap().is_err());
+                })
+                .unwrap();
+        }
     }
 }
@@ -0,0 +1,93 @@
+use std::time::Duration;
+
+mod test {
+    use super::*;
+
+    #[uringy::test]
+    fn runs_inside_runtime() {
+        uringy::runtime::spawn(|| ()).join().unwrap();
+    }
+
+    #[uringy::test]
+    fn returns_result() -> std::io::Result<()> {
+        Ok(())
+    }
+
+    #[uringy::test]
+    #[should_panic(expected = "oops")]
+    fn reports_panic() {
+        panic!("oops");
+    }
+
+    #[uringy::test(timeout = Duration::from_millis(10))]
+    #[should_panic(expected = "timed out")]
+    fn times_out() {
+        uringy::time::sleep(Duration::from_secs(10)).unwrap();
+    }
+
+    #[uringy::test(timeout = Duration::from_secs(10))]
+    fn completes_before_timeout() {
+        uringy::time::sleep(Duration::from_millis(1)).unwrap();
+    }
+
+    #[uringy::test(stack_size = 512 * 1024)]
+    fn configures_stack_size() {
+        let buffer = [1u8; 256 * 1024];
+        assert_eq!(std::hint::black_box(&buffer)[0], 1);
+    }
+
+    #[uringy::test(simulated_clock, timeout = Duration::from_secs(1))]
+    fn simulates_clock() {
+        let before = uringy::time::now();
+        uringy::time::sleep(Duration::from_secs(60)).unwrap();
+        assert!(uringy::time::now() - before >= Duration::from_secs(60));
+    }
+}
+
+mod start {
+    use std::process::ExitCode;
+
+    use super::*;
+
+    fn same(a: ExitCode, b: ExitCode) -> bool {
+        format!("{a:?}") == format!("{b:?}")
+    }
+
+    #[uringy::start]
+    fn runs_inside_runtime() {
+        uringy::runtime::spawn(|| ()).join().unwrap();
+    }
+
+    #[uringy::start(ring_entries = 8, stack_size = 512 * 1024)]
+    fn returns_error() -> Result<(), &'static str> {
+        uringy::time::sleep(Duration::from_millis(1)).unwrap();
+        Err("oops")
+    }
+
+    #[uringy::start]
+    fn panics() {
+        panic!("oops");
+    }
+
+    #[uringy::start(per_core = true)]
+    fn runs_per_core() -> Result<(), uringy::Error<()>> {
+        uringy::time::sleep(Duration::from_millis(1))?;
+        Ok(())
+    }
+
+    #[test]
+    fn succeeds() {
+        assert!(same(runs_inside_runtime(), ExitCode::SUCCESS));
+        assert!(same(runs_per_core(), ExitCode::SUCCESS));
+    }
+
+    #[test]
+    fn fails_with_error() {
+        assert!(same(returns_error(), ExitCode::FAILURE));
+    }
+
+    #[test]
+    fn fails_with_panic() {
+        assert!(same(panics(), ExitCode::from(101)));
+    }
+}